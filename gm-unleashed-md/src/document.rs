@@ -0,0 +1,1179 @@
+use std::iter::Peekable;
+use super::{ Token, Tokens, Markdown, StyleSpan, Style, Span, Break, SourceToken, SourceRange, source_tokens, push_target_text };
+
+pub type Blocks = Vec<Block>;
+pub type Inlines = Vec<Inline>;
+pub type Diagnostics = Vec<Diagnostic>;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Document {
+    pub blocks : Blocks,
+    pub diagnostics : Diagnostics,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Block {
+    pub kind : BlockKind,
+    pub range : SourceRange,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum BlockKind {
+    Paragraph(Inlines),
+    Heading{ level : u8, content : Inlines },
+    List(Vec<ListItem>),
+    Quote(Blocks),
+    /// Blocks only the GM may see.
+    Secret(Blocks),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ListItem {
+    pub number : Option<u32>,
+    pub content : Inlines,
+    pub children : Blocks,
+    pub range : SourceRange,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Inline {
+    pub kind : InlineKind,
+    pub range : SourceRange,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum InlineKind {
+    Text(String),
+    Emphasis(Inlines),
+    Strong(Inlines),
+    Link{ target : String, content : Inlines },
+    /// A `[[Target|text]]` link; without explicit text it shows its target.
+    WikiLink{ target : String, text : Option<String> },
+    Code(String),
+    /// A `#tag`, stored without the `#`.
+    Tag(String),
+    /// Text only the GM may see.
+    Secret(Inlines),
+    LineBreak,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Node<'a> {
+    Block(&'a Block),
+    ListItem(&'a ListItem),
+    Inline(&'a Inline),
+}
+
+impl<'a> Node<'a> {
+    pub fn range(&self) -> SourceRange {
+        match self {
+            Node::Block(block) => block.range.clone(),
+            Node::ListItem(item) => item.range.clone(),
+            Node::Inline(inline) => inline.range.clone(),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Diagnostic {
+    pub line : usize,
+    pub range : SourceRange,
+    pub kind : DiagnosticKind,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DiagnosticKind {
+    UnclosedEmphasis,
+    UnclosedStrong,
+    UnclosedLink,
+    UnclosedLinkTarget,
+    UnmatchedLinkMiddle,
+    UnclosedSecret,
+    UnmatchedSecretClose,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        let message = match self.kind {
+            DiagnosticKind::UnclosedEmphasis => "'*' is never closed",
+            DiagnosticKind::UnclosedStrong => "'**' is never closed",
+            DiagnosticKind::UnclosedLink => "'[' is never followed by '](target)'",
+            DiagnosticKind::UnclosedLinkTarget => "link target is never closed with ')'",
+            DiagnosticKind::UnmatchedLinkMiddle => "'](' has no matching '['",
+            DiagnosticKind::UnclosedSecret => "'{{' is never closed with '}}'",
+            DiagnosticKind::UnmatchedSecretClose => "'}}' has no matching '{{'",
+        };
+        write!(f, "Line {}: {}", self.line + 1, message)
+    }
+}
+
+pub fn parse_document(tokens : Tokens) -> Document {
+    let mut builder = DocumentBuilder {
+        diagnostics : Diagnostics::new(),
+    };
+    let blocks = builder.blocks(split_lines(source_tokens(tokens)));
+    Document {
+        blocks,
+        diagnostics : builder.diagnostics,
+    }
+}
+
+impl Document {
+    /// Returns the nodes whose source range contains `offset`, outermost first.
+    pub fn nodes_at(&self, offset : usize) -> Vec<Node<'_>> {
+        let mut nodes = Vec::new();
+        blocks_at(&self.blocks, offset, &mut nodes);
+        nodes
+    }
+
+    pub fn node_at(&self, offset : usize) -> Option<Node<'_>> {
+        self.nodes_at(offset).pop()
+    }
+
+    /// Returns every link, in source order.
+    pub fn links(&self) -> Vec<&Inline> {
+        let mut links = Vec::new();
+        visit_inlines(&self.blocks, &mut |inline| {
+            if let InlineKind::Link{ .. } | InlineKind::WikiLink{ .. } = &inline.kind {
+                links.push(inline);
+            }
+        });
+        links
+    }
+}
+
+impl Document {
+    /// Retargets every link for which `rewrite` returns a new target, returning the number of links changed.
+    pub fn rewrite_links<F>(&mut self, mut rewrite : F) -> usize
+        where F : FnMut(&str) -> Option<String>
+    {
+        let mut count = 0;
+        for_each_line(&mut self.blocks, &mut |inlines| { count += retarget_inlines(inlines, &mut rewrite) });
+        count
+    }
+
+    /// Replaces every link for which `should_unlink` returns true by its content, returning the number of links removed.
+    pub fn unlink<F>(&mut self, mut should_unlink : F) -> usize
+        where F : FnMut(&str) -> bool
+    {
+        let mut count = 0;
+        for_each_line(&mut self.blocks, &mut |inlines| { count += unlink_inlines(inlines, &mut should_unlink) });
+        count
+    }
+
+    /// Removes every secret block and span, leaving only what the players may see. Blocks, list items and emphasis
+    /// that held nothing but secrets are removed with them.
+    pub fn strip_secrets(&mut self) {
+        strip_blocks(&mut self.blocks);
+    }
+
+    /// The source ranges of every secret block and span, not counting secrets nested in other secrets.
+    pub fn secret_ranges(&self) -> Vec<SourceRange> {
+        let mut ranges = Vec::new();
+        block_secret_ranges(&self.blocks, &mut ranges);
+        ranges
+    }
+
+    /// The targets of every link, in document order.
+    pub fn link_targets(&self) -> Vec<&str> {
+        self.links().into_iter().filter_map(|link| {
+            match &link.kind {
+                InlineKind::Link{ target, .. } | InlineKind::WikiLink{ target, .. } => Some(target.as_str()),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// The names of every tag, in document order.
+    pub fn tags(&self) -> Vec<&str> {
+        let mut tags = Vec::new();
+        visit_inlines(&self.blocks, &mut |inline| {
+            if let InlineKind::Tag(name) = &inline.kind {
+                tags.push(name.as_str());
+            }
+        });
+        tags
+    }
+}
+
+/// Returns whether anything was removed.
+fn strip_blocks(blocks : &mut Blocks) -> bool {
+    let mut stripped = false;
+    blocks.retain_mut(|block| {
+        let (removed, now_empty) = match &mut block.kind {
+            BlockKind::Secret(_) => (true, true),
+            BlockKind::Paragraph(content) | BlockKind::Heading{ content, .. } => {
+                let removed = strip_inlines(content);
+                (removed, content.iter().all(|inline| { matches!(&inline.kind, InlineKind::Text(text) if text.trim().is_empty()) }))
+            }
+            BlockKind::List(items) => {
+                let mut removed = false;
+                items.retain_mut(|item| {
+                    let item_removed = strip_inlines(&mut item.content) | strip_blocks(&mut item.children);
+                    removed |= item_removed;
+                    !(item_removed && item.content.is_empty() && item.children.is_empty())
+                });
+                (removed, items.is_empty())
+            }
+            BlockKind::Quote(blocks) => (strip_blocks(blocks), blocks.is_empty()),
+        };
+        stripped |= removed;
+        !(removed && now_empty)
+    });
+    stripped
+}
+
+fn strip_inlines(inlines : &mut Inlines) -> bool {
+    let mut stripped = false;
+    for inline in std::mem::take(inlines) {
+        let range = inline.range;
+        let (removed, kept) = match inline.kind {
+            InlineKind::Secret(_) => (true, None),
+            InlineKind::Emphasis(mut content) => (strip_inlines(&mut content), Some(content).filter(|content| { !content.is_empty() }).map(InlineKind::Emphasis)),
+            InlineKind::Strong(mut content) => (strip_inlines(&mut content), Some(content).filter(|content| { !content.is_empty() }).map(InlineKind::Strong)),
+            InlineKind::Link{ target, mut content } => {
+                let removed = strip_inlines(&mut content);
+                (removed, Some(content).filter(|content| { !removed || !content.is_empty() }).map(|content| { InlineKind::Link{ target, content } }))
+            }
+            kind => (false, Some(kind)),
+        };
+        stripped |= removed;
+        if let Some(kind) = kept {
+            push_inline(inlines, Inline{ kind, range });
+        }
+    }
+    stripped
+}
+
+fn block_secret_ranges(blocks : &[Block], ranges : &mut Vec<SourceRange>) {
+    for block in blocks {
+        match &block.kind {
+            BlockKind::Secret(_) => ranges.push(block.range.clone()),
+            BlockKind::Paragraph(content) | BlockKind::Heading{ content, .. } => inline_secret_ranges(content, ranges),
+            BlockKind::List(items) => {
+                for item in items {
+                    inline_secret_ranges(&item.content, ranges);
+                    block_secret_ranges(&item.children, ranges);
+                }
+            }
+            BlockKind::Quote(blocks) => block_secret_ranges(blocks, ranges),
+        }
+    }
+}
+
+fn inline_secret_ranges(inlines : &[Inline], ranges : &mut Vec<SourceRange>) {
+    for inline in inlines {
+        match &inline.kind {
+            InlineKind::Secret(_) => ranges.push(inline.range.clone()),
+            InlineKind::Emphasis(content) | InlineKind::Strong(content) | InlineKind::Link{ content, .. } => inline_secret_ranges(content, ranges),
+            InlineKind::Text(_) | InlineKind::WikiLink{ .. } | InlineKind::Code(_) | InlineKind::Tag(_) | InlineKind::LineBreak => {}
+        }
+    }
+}
+
+/// Visits every inline, parents before their content, in document order.
+fn visit_inlines<'a, F>(blocks : &'a [Block], visit : &mut F)
+    where F : FnMut(&'a Inline)
+{
+    for block in blocks {
+        match &block.kind {
+            BlockKind::Paragraph(content) | BlockKind::Heading{ content, .. } => visit_inline_content(content, visit),
+            BlockKind::List(items) => {
+                for item in items {
+                    visit_inline_content(&item.content, visit);
+                    visit_inlines(&item.children, visit);
+                }
+            }
+            BlockKind::Quote(blocks) | BlockKind::Secret(blocks) => visit_inlines(blocks, visit),
+        }
+    }
+}
+
+fn visit_inline_content<'a, F>(inlines : &'a [Inline], visit : &mut F)
+    where F : FnMut(&'a Inline)
+{
+    for inline in inlines {
+        visit(inline);
+        match &inline.kind {
+            InlineKind::Emphasis(content) | InlineKind::Strong(content) | InlineKind::Secret(content) | InlineKind::Link{ content, .. } => {
+                visit_inline_content(content, visit)
+            }
+            InlineKind::Text(_) | InlineKind::WikiLink{ .. } | InlineKind::Code(_) | InlineKind::Tag(_) | InlineKind::LineBreak => {}
+        }
+    }
+}
+
+fn for_each_line<F>(blocks : &mut [Block], edit : &mut F)
+    where F : FnMut(&mut Inlines)
+{
+    for block in blocks {
+        match &mut block.kind {
+            BlockKind::Paragraph(content) | BlockKind::Heading{ content, .. } => edit(content),
+            BlockKind::List(items) => {
+                for item in items {
+                    edit(&mut item.content);
+                    for_each_line(&mut item.children, edit);
+                }
+            }
+            BlockKind::Quote(blocks) | BlockKind::Secret(blocks) => for_each_line(blocks, edit),
+        }
+    }
+}
+
+fn retarget_inlines<F>(inlines : &mut [Inline], rewrite : &mut F) -> usize
+    where F : FnMut(&str) -> Option<String>
+{
+    let mut count = 0;
+    for inline in inlines {
+        match &mut inline.kind {
+            InlineKind::Link{ target, .. } | InlineKind::WikiLink{ target, .. } => {
+                if let Some(new_target) = rewrite(target) {
+                    *target = new_target;
+                    count += 1;
+                }
+            }
+            InlineKind::Emphasis(content) | InlineKind::Strong(content) | InlineKind::Secret(content) => count += retarget_inlines(content, rewrite),
+            InlineKind::Text(_) | InlineKind::Code(_) | InlineKind::Tag(_) | InlineKind::LineBreak => {}
+        }
+    }
+    count
+}
+
+fn unlink_inlines<F>(inlines : &mut Inlines, should_unlink : &mut F) -> usize
+    where F : FnMut(&str) -> bool
+{
+    let mut count = 0;
+    for inline in std::mem::take(inlines) {
+        match inline.kind {
+            InlineKind::Link{ target, content } if should_unlink(&target) => {
+                for inline in content {
+                    push_inline(inlines, inline);
+                }
+                count += 1;
+            }
+            InlineKind::WikiLink{ target, text } if should_unlink(&target) => {
+                push_text(inlines, text.as_deref().unwrap_or(&target), inline.range);
+                count += 1;
+            }
+            InlineKind::Emphasis(mut content) => {
+                count += unlink_inlines(&mut content, should_unlink);
+                inlines.push(Inline{ kind : InlineKind::Emphasis(content), range : inline.range });
+            }
+            InlineKind::Strong(mut content) => {
+                count += unlink_inlines(&mut content, should_unlink);
+                inlines.push(Inline{ kind : InlineKind::Strong(content), range : inline.range });
+            }
+            InlineKind::Secret(mut content) => {
+                count += unlink_inlines(&mut content, should_unlink);
+                inlines.push(Inline{ kind : InlineKind::Secret(content), range : inline.range });
+            }
+            kind => push_inline(inlines, Inline{ kind, range : inline.range }),
+        }
+    }
+    count
+}
+
+fn blocks_at<'a>(blocks : &'a [Block], offset : usize, nodes : &mut Vec<Node<'a>>) {
+    if let Some(block) = blocks.iter().find(|block| { block.range.contains(&offset) }) {
+        nodes.push(Node::Block(block));
+        match &block.kind {
+            BlockKind::Paragraph(content) | BlockKind::Heading{ content, .. } => inlines_at(content, offset, nodes),
+            BlockKind::List(items) => {
+                if let Some(item) = items.iter().find(|item| { item.range.contains(&offset) }) {
+                    nodes.push(Node::ListItem(item));
+                    inlines_at(&item.content, offset, nodes);
+                    blocks_at(&item.children, offset, nodes);
+                }
+            }
+            BlockKind::Quote(blocks) | BlockKind::Secret(blocks) => blocks_at(blocks, offset, nodes),
+        }
+    }
+}
+
+fn inlines_at<'a>(inlines : &'a [Inline], offset : usize, nodes : &mut Vec<Node<'a>>) {
+    if let Some(inline) = inlines.iter().find(|inline| { inline.range.contains(&offset) }) {
+        nodes.push(Node::Inline(inline));
+        match &inline.kind {
+            InlineKind::Emphasis(content) | InlineKind::Strong(content) | InlineKind::Link{ content, .. } | InlineKind::Secret(content) => {
+                inlines_at(content, offset, nodes)
+            }
+            InlineKind::Text(_) | InlineKind::WikiLink{ .. } | InlineKind::Code(_) | InlineKind::Tag(_) | InlineKind::LineBreak => {}
+        }
+    }
+}
+
+struct Line {
+    number : usize,
+    tokens : Vec<SourceToken>,
+    range : SourceRange,
+    line_break : Option<SourceToken>,
+}
+
+impl Line {
+    fn new(number : usize, start : usize) -> Self {
+        Line {
+            number,
+            tokens : Vec::new(),
+            range : start..start,
+            line_break : None,
+        }
+    }
+
+    fn first(&self) -> Option<&Token> { self.tokens.first().map(|token| { &token.token }) }
+
+    /// A line is blank when it has no visible content, which includes lines holding only empty emphasis.
+    fn is_blank(&self) -> bool {
+        let mut diagnostics = Diagnostics::new();
+        let mut parser = InlineParser::new(self.number, &mut diagnostics);
+        for token in &self.tokens {
+            parser.token(token.clone());
+        }
+        parser.finish().iter().all(|inline| { matches!(&inline.kind, InlineKind::Text(text) if text.trim().is_empty()) })
+    }
+
+    fn is_plain(&self) -> bool {
+        !self.is_blank() && !matches!(self.first(), Some(Token::Quote) | Some(Token::SecretBlock) | Some(Token::Heading(_)) | Some(Token::ListItem(_)))
+    }
+
+    fn list_indent(&self) -> usize {
+        match self.first() {
+            Some(Token::ListItem(marker)) => marker.indent(),
+            _ => 0,
+        }
+    }
+
+    fn strip_marker(mut self) -> Line {
+        let marker = self.tokens.remove(0);
+        self.range.start = marker.range.end;
+        self
+    }
+}
+
+fn split_lines(tokens : Vec<SourceToken>) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut line = Line::new(0, 0);
+    for token in tokens {
+        if token.token == Token::LineBreak {
+            let next_line = Line::new(line.number + 1, token.range.end);
+            line.line_break = Some(token);
+            lines.push(std::mem::replace(&mut line, next_line));
+        } else {
+            line.range.end = token.range.end;
+            line.tokens.push(token);
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+struct DocumentBuilder {
+    diagnostics : Diagnostics,
+}
+
+impl DocumentBuilder {
+    fn blocks(&mut self, lines : Vec<Line>) -> Blocks {
+        let mut blocks = Blocks::new();
+        let mut lines = lines.into_iter().peekable();
+        while let Some(line) = lines.next() {
+            match line.first() {
+                Some(Token::Quote) | Some(Token::SecretBlock) => {
+                    let marker = line.first().cloned();
+                    let mut range = line.range.clone();
+                    let mut nested = vec![line.strip_marker()];
+                    while let Some(next) = lines.next_if(|next| { next.first() == marker.as_ref() }) {
+                        range.end = next.range.end;
+                        nested.push(next.strip_marker());
+                    }
+                    let nested = self.blocks(nested);
+                    let kind = if marker == Some(Token::Quote) { BlockKind::Quote(nested) } else { BlockKind::Secret(nested) };
+                    blocks.push(Block{ kind, range });
+                }
+                Some(&Token::Heading(level)) => {
+                    let range = line.range.clone();
+                    let content = self.inlines(line.strip_marker());
+                    blocks.push(Block{ kind : BlockKind::Heading{ level, content }, range });
+                }
+                Some(Token::ListItem(_)) => {
+                    let mut entries = vec![line];
+                    while let Some(next) = lines.next_if(|next| { matches!(next.first(), Some(Token::ListItem(_))) }) {
+                        entries.push(next);
+                    }
+                    blocks.append(&mut self.lists(&mut entries.into_iter().peekable(), None));
+                }
+                _ if line.is_blank() => {}
+                _ => {
+                    let mut paragraph = line;
+                    while let Some(next) = lines.next_if(|next| { next.is_plain() }) {
+                        paragraph.tokens.extend(paragraph.line_break.take());
+                        paragraph.tokens.extend(next.tokens);
+                        paragraph.range.end = next.range.end;
+                        paragraph.line_break = next.line_break;
+                    }
+                    let range = paragraph.range.clone();
+                    blocks.push(Block{ kind : BlockKind::Paragraph(self.inlines(paragraph)), range });
+                }
+            }
+        }
+        blocks
+    }
+
+    fn lists<T>(&mut self, entries : &mut Peekable<T>, parent_indent : Option<usize>) -> Blocks
+        where T : Iterator<Item=Line>
+    {
+        let mut lists = Blocks::new();
+        let mut items : Vec<ListItem> = Vec::new();
+        while let Some(line) = entries.next_if(|line| { parent_indent.is_none_or(|parent| { line.list_indent() > parent }) }) {
+            let indent = line.list_indent();
+            let number = match line.first() {
+                Some(Token::ListItem(marker)) => marker.number(),
+                _ => None,
+            };
+            if items.first().is_some_and(|first| { first.number.is_some() != number.is_some() }) {
+                lists.push(list_block(std::mem::take(&mut items)));
+            }
+            let range = line.range.clone();
+            let content = self.inlines(line.strip_marker());
+            let children = self.lists(entries, Some(indent));
+            let end = children.last().map_or(range.end, |child| { child.range.end });
+            items.push(ListItem {
+                number,
+                content,
+                children,
+                range : range.start..end,
+            });
+        }
+        if !items.is_empty() {
+            lists.push(list_block(items));
+        }
+        lists
+    }
+
+    fn inlines(&mut self, line : Line) -> Inlines {
+        let mut parser = InlineParser::new(line.number, &mut self.diagnostics);
+        for token in line.tokens {
+            parser.token(token);
+        }
+        parser.finish()
+    }
+}
+
+fn list_block(items : Vec<ListItem>) -> Block {
+    let range = items[0].range.start..items[items.len() - 1].range.end;
+    Block{ kind : BlockKind::List(items), range }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Marker {
+    Emphasis,
+    Strong,
+    Link,
+    Secret,
+}
+
+impl Marker {
+    fn as_text(self) -> &'static str {
+        match self {
+            Marker::Emphasis => Token::Asterisk.as_text(),
+            Marker::Strong => Token::DoubleAsterisk.as_text(),
+            Marker::Link => Token::OpenSquareBrace.as_text(),
+            Marker::Secret => Token::OpenSecret.as_text(),
+        }
+    }
+}
+
+struct OpenInline {
+    marker : Marker,
+    content : Inlines,
+    start : usize,
+    line : usize,
+    target : Option<String>,
+    target_tokens : Vec<SourceToken>,
+    target_range : SourceRange,
+    wiki_links : Vec<SourceToken>,
+}
+
+impl OpenInline {
+    fn new(marker : Marker, start : usize, line : usize) -> Self {
+        OpenInline {
+            marker,
+            content : Inlines::new(),
+            start,
+            line,
+            target : None,
+            target_tokens : Vec::new(),
+            target_range : start..start,
+            wiki_links : Vec::new(),
+        }
+    }
+
+    fn marker_range(&self) -> SourceRange {
+        self.start..self.start + self.marker.as_text().len()
+    }
+
+    fn open_target(&mut self, link_middle : SourceToken) {
+        self.target = Some(String::new());
+        self.target_range = link_middle.range.clone();
+        self.target_tokens.push(link_middle);
+    }
+
+    fn extend_target(&mut self, token : SourceToken) {
+        if let Some(target) = self.target.as_mut() {
+            push_target_text(target, &token.token);
+        }
+        self.target_range.end = token.range.end;
+        self.target_tokens.push(token);
+    }
+
+    fn unclosed(&self) -> Diagnostic {
+        let (kind, range) = match self.marker {
+            Marker::Emphasis => (DiagnosticKind::UnclosedEmphasis, self.marker_range()),
+            Marker::Strong => (DiagnosticKind::UnclosedStrong, self.marker_range()),
+            Marker::Link if self.target.is_some() => (DiagnosticKind::UnclosedLinkTarget, self.target_range.clone()),
+            Marker::Link => (DiagnosticKind::UnclosedLink, self.marker_range()),
+            Marker::Secret => (DiagnosticKind::UnclosedSecret, self.marker_range()),
+        };
+        Diagnostic{ line : self.line, range, kind }
+    }
+
+    fn close(self, end : usize) -> Inline {
+        let kind = match self.marker {
+            Marker::Emphasis => InlineKind::Emphasis(self.content),
+            Marker::Strong => InlineKind::Strong(self.content),
+            Marker::Link => InlineKind::Link{ target : self.target.unwrap_or_default(), content : literal_wiki_links(self.content, &self.wiki_links) },
+            Marker::Secret => InlineKind::Secret(self.content),
+        };
+        Inline{ kind, range : self.start..end }
+    }
+
+    fn unwrap_into(self, parent : &mut Inlines) {
+        push_text(parent, self.marker.as_text(), self.marker_range());
+        for inline in self.content {
+            push_inline(parent, inline);
+        }
+        for token in self.target_tokens {
+            push_literal(parent, token);
+        }
+    }
+}
+
+/// Links do not nest, so the shorthand inside link text is literal once the link turns out to be one.
+fn literal_wiki_links(inlines : Inlines, wiki_links : &[SourceToken]) -> Inlines {
+    let mut result = Inlines::new();
+    for Inline{ kind, range } in inlines {
+        match kind {
+            kind @ InlineKind::WikiLink{ .. } => match wiki_links.iter().find(|link| { link.range == range }) {
+                Some(link) => push_literal(&mut result, link.clone()),
+                None => result.push(Inline{ kind, range }),
+            },
+            InlineKind::Emphasis(content) => result.push(Inline{ kind : InlineKind::Emphasis(literal_wiki_links(content, wiki_links)), range }),
+            InlineKind::Strong(content) => result.push(Inline{ kind : InlineKind::Strong(literal_wiki_links(content, wiki_links)), range }),
+            InlineKind::Secret(content) => result.push(Inline{ kind : InlineKind::Secret(literal_wiki_links(content, wiki_links)), range }),
+            kind => push_inline(&mut result, Inline{ kind, range }),
+        }
+    }
+    result
+}
+
+/// Adds a token that is not markup where it appears.
+fn push_literal(inlines : &mut Inlines, token : SourceToken) {
+    let SourceToken{ token, range } = token;
+    match token {
+        Token::LineBreak => inlines.push(Inline{ kind : InlineKind::LineBreak, range }),
+        Token::Escape(escape) => push_text(inlines, &escape.character().to_string(), range),
+        Token::Code(code) => inlines.push(Inline{ kind : InlineKind::Code(code.content().to_string()), range }),
+        token => push_text(inlines, token.as_text(), range),
+    }
+}
+
+fn push_text(inlines : &mut Inlines, text : &str, range : SourceRange) {
+    if let Some(Inline{ kind : InlineKind::Text(last), range : last_range }) = inlines.last_mut() {
+        last.push_str(text);
+        last_range.end = range.end;
+    } else {
+        inlines.push(Inline{ kind : InlineKind::Text(text.to_string()), range });
+    }
+}
+
+fn push_inline(inlines : &mut Inlines, inline : Inline) {
+    match inline.kind {
+        InlineKind::Text(text) => push_text(inlines, &text, inline.range),
+        kind => inlines.push(Inline{ kind, range : inline.range }),
+    }
+}
+
+struct InlineParser<'a> {
+    root : Inlines,
+    open : Vec<OpenInline>,
+    line : usize,
+    diagnostics : &'a mut Diagnostics,
+}
+
+impl<'a> InlineParser<'a> {
+    fn new(line : usize, diagnostics : &'a mut Diagnostics) -> Self {
+        InlineParser {
+            root : Inlines::new(),
+            open : Vec::new(),
+            line,
+            diagnostics,
+        }
+    }
+
+    fn content(&mut self) -> &mut Inlines {
+        match self.open.last_mut() {
+            Some(open) => &mut open.content,
+            None => &mut self.root,
+        }
+    }
+
+    fn in_link_target(&self) -> bool {
+        self.open.last().is_some_and(|open| { open.target.is_some() })
+    }
+
+    fn open_link(&self) -> Option<usize> {
+        self.open.iter().rposition(|open| { open.marker == Marker::Link })
+    }
+
+    /// Emphasis cannot cross the edges of link text or a secret span.
+    fn innermost_scope(&self) -> Option<usize> {
+        self.open.iter().rposition(|open| { open.marker == Marker::Link || open.marker == Marker::Secret })
+    }
+
+    fn close_top(&mut self, end : usize) {
+        let open = self.open.pop().unwrap();
+        // An empty link or secret is still something the author wrote; empty emphasis is dropped.
+        if open.marker == Marker::Link || open.marker == Marker::Secret || !open.content.is_empty() {
+            let closed = open.close(end);
+            push_inline(self.content(), closed);
+        }
+    }
+
+    fn unwrap_top(&mut self) {
+        let open = self.open.pop().unwrap();
+        self.diagnostics.push(open.unclosed());
+        if open.marker == Marker::Secret {
+            // An unclosed secret hides the rest of its block instead of showing it to players.
+            let end = open.content.last().map_or(open.marker_range().end, |inline| { inline.range.end });
+            let closed = open.close(end);
+            push_inline(self.content(), closed);
+        } else {
+            open.unwrap_into(self.content());
+        }
+    }
+
+    /// A '}}' without its '{{' hides everything before it in the block, for the same reason.
+    fn close_unopened_secret(&mut self, range : SourceRange) {
+        while !self.open.is_empty() {
+            self.unwrap_top();
+        }
+        self.diagnostics.push(Diagnostic{ line : self.line, range : range.clone(), kind : DiagnosticKind::UnmatchedSecretClose });
+        let start = self.root.first().map_or(range.start, |inline| { inline.range.start });
+        let content = std::mem::take(&mut self.root);
+        self.root.push(Inline{ kind : InlineKind::Secret(content), range : start..range.end });
+    }
+
+    fn toggle(&mut self, marker : Marker, range : SourceRange) {
+        let scope_start = self.innermost_scope().map_or(0, |idx| { idx + 1 });
+        match self.open[scope_start..].iter().rposition(|open| { open.marker == marker }) {
+            Some(idx) => {
+                let closing = scope_start + idx;
+                let mut reopened = Vec::new();
+                while self.open.len() > closing + 1 {
+                    reopened.push(self.open.last().unwrap().marker);
+                    self.close_top(range.start);
+                }
+                self.close_top(range.end);
+                for marker in reopened.into_iter().rev() {
+                    self.open.push(OpenInline::new(marker, range.end, self.line));
+                }
+            }
+            None => self.open.push(OpenInline::new(marker, range.start, self.line)),
+        }
+    }
+
+    fn token(&mut self, token : SourceToken) {
+        if self.in_link_target() {
+            match token.token {
+                Token::CloseRoundBrace => self.close_top(token.range.end),
+                Token::LineBreak => {
+                    self.line += 1;
+                    self.open.last_mut().unwrap().extend_target(token);
+                }
+                _ => self.open.last_mut().unwrap().extend_target(token),
+            }
+            return;
+        }
+        let SourceToken{ token, range } = token;
+        match token {
+            Token::Asterisk => self.toggle(Marker::Emphasis, range),
+            Token::DoubleAsterisk => self.toggle(Marker::Strong, range),
+            Token::OpenSquareBrace if self.open_link().is_none() => self.open.push(OpenInline::new(Marker::Link, range.start, self.line)),
+            Token::LinkMiddle if self.open_link().is_some() => {
+                while self.open.last().is_some_and(|open| { open.marker != Marker::Link }) {
+                    self.unwrap_top();
+                }
+                self.open.last_mut().unwrap().open_target(SourceToken{ token, range });
+            }
+            // Inside link text the shorthand is a link until the text is closed by '](', as a '[' may never be.
+            Token::WikiLink(link) => {
+                if let Some(idx) = self.open_link() {
+                    self.open[idx].wiki_links.push(SourceToken{ token : Token::WikiLink(link.clone()), range : range.clone() });
+                }
+                let kind = InlineKind::WikiLink{ target : link.target().to_string(), text : link.explicit_text().map(str::to_string) };
+                self.content().push(Inline{ kind, range });
+            }
+            Token::LinkMiddle => {
+                self.diagnostics.push(Diagnostic{ line : self.line, range : range.clone(), kind : DiagnosticKind::UnmatchedLinkMiddle });
+                push_text(self.content(), token.as_text(), range);
+            }
+            Token::Tag(tag) => self.content().push(Inline{ kind : InlineKind::Tag(tag.name().to_string()), range }),
+            Token::OpenSecret => self.open.push(OpenInline::new(Marker::Secret, range.start, self.line)),
+            Token::CloseSecret if self.innermost_scope().is_some_and(|idx| { self.open[idx].marker == Marker::Secret }) => {
+                while self.open.last().is_some_and(|open| { open.marker != Marker::Secret }) {
+                    self.unwrap_top();
+                }
+                self.close_top(range.end);
+            }
+            Token::CloseSecret if !self.open.iter().any(|open| { open.marker == Marker::Secret }) => self.close_unopened_secret(range),
+            Token::CloseSecret => {
+                self.diagnostics.push(Diagnostic{ line : self.line, range : range.clone(), kind : DiagnosticKind::UnmatchedSecretClose });
+                push_text(self.content(), token.as_text(), range);
+            }
+            Token::LineBreak => {
+                self.line += 1;
+                push_literal(self.content(), SourceToken{ token, range });
+            }
+            token => push_literal(self.content(), SourceToken{ token, range }),
+        }
+    }
+
+    fn finish(mut self) -> Inlines {
+        while !self.open.is_empty() {
+            self.unwrap_top();
+        }
+        self.root
+    }
+}
+
+impl Document {
+    pub fn to_markdown(&self) -> Markdown {
+        let mut flattener = Flattener {
+            markdown : Markdown {
+                text : Vec::new(),
+                styles : Vec::new(),
+                breaks : Vec::new(),
+                diagnostics : self.diagnostics.clone(),
+            },
+        };
+        flattener.blocks(&self.blocks, 0);
+        flattener.finish()
+    }
+
+    /// The text a reader sees, without any markup. Lines end with a line break, and paragraphs are separated by a
+    /// blank line.
+    pub fn plain_text(&self) -> String {
+        let markdown = self.to_markdown();
+        let mut text = String::new();
+        let mut breaks = markdown.breaks.iter().peekable();
+        for (idx, piece) in markdown.text.iter().enumerate() {
+            while breaks.next_if(|line_break| { line_break.pos == idx }).is_some() {
+                text.push_str(Token::LineBreak.as_text());
+            }
+            text.push_str(piece);
+        }
+        for _ in breaks {
+            text.push_str(Token::LineBreak.as_text());
+        }
+        text
+    }
+}
+
+struct Flattener {
+    markdown : Markdown,
+}
+
+impl Flattener {
+    fn spanned<F>(&mut self, style : Style, source : &SourceRange, flatten : F)
+        where F : FnOnce(&mut Flattener)
+    {
+        let start = self.markdown.text.len();
+        flatten(self);
+        let end = self.markdown.text.len();
+        if end > start {
+            self.markdown.styles.push(StyleSpan{ style, span : Span{ start, end : end - 1 }, source : source.clone() });
+        }
+    }
+
+    fn line_break(&mut self) {
+        self.markdown.breaks.push(Break{ pos : self.markdown.text.len() });
+    }
+
+    fn blocks(&mut self, blocks : &[Block], depth : usize) {
+        for (idx, block) in blocks.iter().enumerate() {
+            if idx > 0 {
+                self.line_break();
+                if let (BlockKind::Paragraph(_), BlockKind::Paragraph(_)) | (BlockKind::Quote(_), BlockKind::Quote(_)) | (BlockKind::Secret(_), BlockKind::Secret(_))
+                    = (&blocks[idx - 1].kind, &block.kind) {
+                    self.line_break();
+                }
+            }
+            self.block(block, depth);
+        }
+    }
+
+    fn block(&mut self, block : &Block, depth : usize) {
+        match &block.kind {
+            BlockKind::Paragraph(content) => self.inlines(content),
+            BlockKind::Heading{ level, content } => self.spanned(Style::Heading{ level : *level }, &block.range, |this| { this.inlines(content) }),
+            BlockKind::List(items) => {
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        self.line_break();
+                    }
+                    self.spanned(Style::ListItem{ depth, number : item.number }, &item.range, |this| { this.inlines(&item.content) });
+                    if !item.children.is_empty() {
+                        self.line_break();
+                        self.blocks(&item.children, depth + 1);
+                    }
+                }
+            }
+            BlockKind::Quote(blocks) => self.spanned(Style::Quote, &block.range, |this| { this.blocks(blocks, depth) }),
+            BlockKind::Secret(blocks) => self.spanned(Style::Secret, &block.range, |this| { this.blocks(blocks, depth) }),
+        }
+    }
+
+    fn inlines(&mut self, inlines : &[Inline]) {
+        for inline in inlines {
+            match &inline.kind {
+                InlineKind::Text(text) => self.markdown.text.push(text.clone()),
+                InlineKind::Emphasis(content) => self.spanned(Style::Italic, &inline.range, |this| { this.inlines(content) }),
+                InlineKind::Strong(content) => self.spanned(Style::Bold, &inline.range, |this| { this.inlines(content) }),
+                InlineKind::Link{ target, content } => self.spanned(Style::Link{ target : target.clone() }, &inline.range, |this| { this.inlines(content) }),
+                InlineKind::WikiLink{ target, text } => self.spanned(Style::Link{ target : target.clone() }, &inline.range, |this| {
+                    this.markdown.text.push(text.as_ref().unwrap_or(target).clone())
+                }),
+                InlineKind::Code(code) => self.spanned(Style::Code, &inline.range, |this| { this.markdown.text.push(code.clone()) }),
+                InlineKind::Tag(name) => self.spanned(Style::Tag, &inline.range, |this| { this.markdown.text.push(format!("#{}", name)) }),
+                InlineKind::Secret(content) => self.spanned(Style::Secret, &inline.range, |this| { this.inlines(content) }),
+                InlineKind::LineBreak => self.line_break(),
+            }
+        }
+    }
+
+    fn finish(mut self) -> Markdown {
+        self.markdown.styles.sort_by_key(|style| { style.span.start });
+        let mut styles : Vec<StyleSpan> = Vec::new();
+        for style in self.markdown.styles {
+            let continued = match style.style {
+                Style::Italic | Style::Bold => styles.iter_mut().find(|previous| {
+                    previous.style == style.style && previous.span.end + 1 == style.span.start
+                }),
+                _ => None,
+            };
+            match continued {
+                Some(previous) => {
+                    previous.span.end = style.span.end;
+                    previous.source.end = style.source.end;
+                }
+                None => styles.push(style),
+            }
+        }
+        self.markdown.styles = styles;
+        self.markdown
+    }
+}
+
+#[cfg(test)]
+mod document_tests {
+    use super::*;
+    use super::super::tokenize;
+    fn document(text : &str) -> Document {
+        parse_document(tokenize(text))
+    }
+    fn outline_inlines(inlines : &[Inline]) -> String {
+        inlines.iter().map(|inline| {
+            match &inline.kind {
+                InlineKind::Text(text) => format!("{:?}", text),
+                InlineKind::Emphasis(content) => format!("em({})", outline_inlines(content)),
+                InlineKind::Strong(content) => format!("strong({})", outline_inlines(content)),
+                InlineKind::Link{ target, content } => format!("link:{}({})", target, outline_inlines(content)),
+                InlineKind::WikiLink{ target, text } => format!("wiki:{}({:?})", target, text),
+                InlineKind::Code(code) => format!("code({:?})", code),
+                InlineKind::Tag(name) => format!("tag:{}", name),
+                InlineKind::Secret(content) => format!("secret({})", outline_inlines(content)),
+                InlineKind::LineBreak => "br".to_string(),
+            }
+        }).collect::<Vec<String>>().join(" ")
+    }
+    fn outline_blocks(blocks : &[Block]) -> String {
+        blocks.iter().map(|block| {
+            match &block.kind {
+                BlockKind::Paragraph(content) => format!("p({})", outline_inlines(content)),
+                BlockKind::Heading{ level, content } => format!("h{}({})", level, outline_inlines(content)),
+                BlockKind::List(items) => format!("list[{}]", items.iter().map(|item| {
+                    let number = item.number.map_or(String::new(), |number| { number.to_string() });
+                    let children = if item.children.is_empty() { String::new() } else { format!(" {}", outline_blocks(&item.children)) };
+                    format!("li{}({}{})", number, outline_inlines(&item.content), children)
+                }).collect::<Vec<String>>().join(" ")),
+                BlockKind::Quote(blocks) => format!("quote({})", outline_blocks(blocks)),
+                BlockKind::Secret(blocks) => format!("secret({})", outline_blocks(blocks)),
+            }
+        }).collect::<Vec<String>>().join(" ")
+    }
+    fn outline(text : &str) -> String {
+        outline_blocks(&document(text).blocks)
+    }
+    #[test]
+    fn paragraphs_are_separated_by_blank_lines() {
+        assert_eq!(outline("one\ntwo\n\nthree"), "p(\"one\" br \"two\") p(\"three\")");
+    }
+    #[test]
+    fn headings_and_inlines() {
+        assert_eq!(outline("## Motivations\nWants **[the crown](Crown)**"), "h2(\"Motivations\") p(\"Wants \" strong(link:Crown(\"the crown\")))");
+    }
+    #[test]
+    fn escapes_and_code_are_literal() {
+        assert_eq!(outline("\\# not a heading \\*x\\* `*code* [a](b)`"), "p(\"# not a heading *x* \" code(\"*code* [a](b)\"))");
+    }
+    #[test]
+    fn wiki_links_do_not_nest() {
+        assert_eq!(outline("[[A]] [x [[B|b]]](C)"), "p(wiki:A(None) \" \" link:C(\"x [[B|b]]\"))");
+    }
+    #[test]
+    fn wiki_links_after_a_stray_bracket() {
+        assert_eq!(outline("[optional] see [[Goblin King]]"), "p(\"[optional] see \" wiki:Goblin King(None))");
+        assert_eq!(outline("[x *[[A]]* [[B]]](C)"), "p(link:C(\"x \" em(\"[[A]]\") \" [[B]]\"))");
+        let document = parse_document(tokenize("[optional] see [[Goblin King]]"));
+        assert_eq!(document.link_targets(), vec!["Goblin King"]);
+    }
+    #[test]
+    fn tags_are_inline_nodes() {
+        assert_eq!(outline("#villain **#act2** [#x](T) [a](#y)"), "p(tag:villain \" \" strong(tag:act2) \" \" link:T(tag:x) \" \" link:#y(\"a\"))");
+    }
+    #[test]
+    fn nested_lists() {
+        assert_eq!(outline("- a\n  1. b\n  2. c\n- d"), "list[li(\"a\" list[li1(\"b\") li2(\"c\")]) li(\"d\")]");
+    }
+    #[test]
+    fn changing_list_type_starts_a_new_list() {
+        assert_eq!(outline("- a\n1. b"), "list[li(\"a\")] list[li1(\"b\")]");
+    }
+    #[test]
+    fn quotes_contain_blocks() {
+        assert_eq!(outline("> # Rumour\n> - *whispered*\nafter"), "quote(h1(\"Rumour\") list[li(em(\"whispered\"))]) p(\"after\")");
+    }
+    #[test]
+    fn secrets_contain_blocks_and_inlines() {
+        assert_eq!(outline("!> # Twist
+!> The *duke* {{is}} the thief
+> quoted"),
+            "secret(h1(\"Twist\") p(\"The \" em(\"duke\") \" \" secret(\"is\") \" the thief\")) quote(p(\"quoted\"))");
+        assert_eq!(outline("a {{b *c}} d*"), "p(\"a \" secret(\"b *c\") \" d*\")");
+        assert_eq!(outline("{b} {{}}"), "p(\"{b} \" secret())");
+    }
+    #[test]
+    fn unmatched_secret_markers_hide_text() {
+        let doc = document("a}} {{b");
+        assert_eq!(outline_blocks(&doc.blocks), "p(secret(\"a\") \" \" secret(\"b\"))");
+        assert_eq!(doc.diagnostics, vec![
+            Diagnostic{ line : 0, range : 1..3, kind : DiagnosticKind::UnmatchedSecretClose },
+            Diagnostic{ line : 0, range : 4..6, kind : DiagnosticKind::UnclosedSecret },
+        ]);
+        assert_eq!(outline("*a [b](c) }} d"), "p(secret(\"*a \" link:c(\"b\") \" \") \" d\")");
+        assert_eq!(outline("[a {{b](c)"), "p(link:c(\"a \" secret(\"b\")))");
+    }
+    #[test]
+    fn unmatched_secret_markers_are_stripped() {
+        for (input, expected) in &[("Public {{secret", "p(\"Public \")"), ("{{the duke\n\nis the thief}}", "")] {
+            let mut doc = document(input);
+            doc.strip_secrets();
+            assert_eq!(outline_blocks(&doc.blocks), *expected);
+        }
+    }
+    #[test]
+    fn secrets_are_stripped() {
+        let mut doc = document("Open {{hidden}} text\n\n{{all hidden}}\n\n!> secret block\n\n- {{x}}\n- *{{y}}* kept\n\n> {{z}}\n\n[{{only}}](Link) [a {{b}}](Link)");
+        doc.strip_secrets();
+        assert_eq!(outline_blocks(&doc.blocks), "p(\"Open  text\") list[li(\" kept\")] p(\" \" link:Link(\"a \"))");
+    }
+    #[test]
+    fn plain_text_drops_markup() {
+        assert_eq!(document("# The *Fort*\nHeld by [[Elara|the queen]] and [x](Y) `code` #tag\n\n- a\n- b\n\n").plain_text(),
+            "The Fort\nHeld by the queen and x code #tag\na\nb");
+    }
+    #[test]
+    fn overlapping_styles_are_split() {
+        assert_eq!(outline("a*b**c*d**"), "p(\"a\" em(\"b\" strong(\"c\")) strong(\"d\"))");
+    }
+    #[test]
+    fn unclosed_markers_stay_literal() {
+        let doc = document("a *b [c](d");
+        assert_eq!(outline_blocks(&doc.blocks), "p(\"a *b [c](d\")");
+        assert_eq!(doc.blocks[0].range, 0..10);
+        assert_eq!(doc.diagnostics, vec![
+            Diagnostic{ line : 0, range : 7..10, kind : DiagnosticKind::UnclosedLinkTarget },
+            Diagnostic{ line : 0, range : 2..3, kind : DiagnosticKind::UnclosedEmphasis },
+        ]);
+    }
+    #[test]
+    fn stray_link_middle() {
+        for input in &["](x)", "](x)\nb"] {
+            let doc = document(input);
+            assert_eq!(doc.diagnostics, vec![Diagnostic{ line : 0, range : 0..2, kind : DiagnosticKind::UnmatchedLinkMiddle }]);
+        }
+        assert_eq!(outline("](x)"), "p(\"](x)\")");
+    }
+    #[test]
+    fn diagnostics_report_the_opening_line() {
+        let doc = document("fine\n\n# **bold\n- [text\nmore");
+        assert_eq!(doc.diagnostics, vec![
+            Diagnostic{ line : 2, range : 8..10, kind : DiagnosticKind::UnclosedStrong },
+            Diagnostic{ line : 3, range : 17..18, kind : DiagnosticKind::UnclosedLink },
+        ]);
+        assert_eq!(doc.diagnostics[0].to_string(), "Line 3: '**' is never closed");
+    }
+    #[test]
+    fn balanced_markup_has_no_diagnostics() {
+        assert_eq!(document("*a* **b** [c](d) (e) f]").diagnostics.len(), 0);
+    }
+    #[test]
+    fn nodes_know_their_source() {
+        let text = "# Title\n\n> - *quoted* [link](Target)\ntext";
+        let doc = document(text);
+        let ranges : Vec<&str> = doc.blocks.iter().map(|block| { &text[block.range.clone()] }).collect();
+        assert_eq!(ranges, vec!["# Title", "> - *quoted* [link](Target)", "text"]);
+        match &doc.blocks[1].kind {
+            BlockKind::Quote(blocks) => match &blocks[0].kind {
+                BlockKind::List(items) => {
+                    assert_eq!(&text[items[0].range.clone()], "- *quoted* [link](Target)");
+                    let inlines : Vec<&str> = items[0].content.iter().map(|inline| { &text[inline.range.clone()] }).collect();
+                    assert_eq!(inlines, vec!["*quoted*", " ", "[link](Target)"]);
+                }
+                _ => panic!("Expected a list"),
+            },
+            _ => panic!("Expected a quote"),
+        }
+    }
+    #[test]
+    fn offsets_map_to_nodes() {
+        let text = "para\n- a **bold** item";
+        let doc = document(text);
+        let offset = text.find("bold").unwrap();
+        let nodes = doc.nodes_at(offset);
+        assert_eq!(nodes.len(), 4);
+        assert!(matches!(nodes[0], Node::Block(Block{ kind : BlockKind::List(_), .. })));
+        assert!(matches!(nodes[1], Node::ListItem(_)));
+        assert!(matches!(nodes[2], Node::Inline(Inline{ kind : InlineKind::Strong(_), .. })));
+        assert_eq!(&text[doc.node_at(offset).unwrap().range()], "bold");
+        assert!(doc.node_at(4).is_none());
+    }
+    #[test]
+    fn compatibility_layer() {
+        let md = document("> quoted\n\n- item").to_markdown();
+        assert_eq!(md.text, vec!["quoted", "item"]);
+        assert_eq!(md.styles, vec![
+            StyleSpan{ span : Span{ start : 0, end : 0 }, style : Style::Quote, source : 0..8 },
+            StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::ListItem{ depth : 0, number : None }, source : 10..16 },
+        ]);
+        assert_eq!(md.breaks, vec![Break{ pos : 1 }]);
+    }
+}
@@ -0,0 +1,169 @@
+use super::{ Document, Block, BlockKind, Inline, InlineKind };
+
+pub fn escape_html(text : &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Renders the document as an HTML fragment. `link_href` maps a link target to the page it points at;
+/// targets it returns `None` for are rendered as broken links.
+pub fn to_html<F>(document : &Document, link_href : F) -> String
+    where F : FnMut(&str) -> Option<String>
+{
+    let mut writer = HtmlWriter {
+        html : String::new(),
+        link_href,
+    };
+    writer.blocks(&document.blocks);
+    writer.html
+}
+
+struct HtmlWriter<F> {
+    html : String,
+    link_href : F,
+}
+
+impl<F> HtmlWriter<F>
+    where F : FnMut(&str) -> Option<String>
+{
+    fn blocks(&mut self, blocks : &[Block]) {
+        for block in blocks {
+            self.block(block);
+        }
+    }
+
+    fn block(&mut self, block : &Block) {
+        match &block.kind {
+            BlockKind::Paragraph(content) => {
+                self.html.push_str("<p>");
+                self.inlines(content);
+                self.html.push_str("</p>\n");
+            }
+            BlockKind::Heading{ level, content } => {
+                self.html.push_str(&format!("<h{}>", level));
+                self.inlines(content);
+                self.html.push_str(&format!("</h{}>\n", level));
+            }
+            BlockKind::List(items) => {
+                let tag = match items[0].number {
+                    Some(1) => "ol".to_string(),
+                    Some(start) => format!("ol start=\"{}\"", start),
+                    None => "ul".to_string(),
+                };
+                self.html.push_str(&format!("<{}>\n", tag));
+                for item in items {
+                    self.html.push_str("<li>");
+                    self.inlines(&item.content);
+                    if !item.children.is_empty() {
+                        self.html.push('\n');
+                        self.blocks(&item.children);
+                    }
+                    self.html.push_str("</li>\n");
+                }
+                self.html.push_str(if items[0].number.is_some() { "</ol>\n" } else { "</ul>\n" });
+            }
+            BlockKind::Quote(blocks) => {
+                self.html.push_str("<blockquote>\n");
+                self.blocks(blocks);
+                self.html.push_str("</blockquote>\n");
+            }
+            BlockKind::Secret(blocks) => {
+                self.html.push_str("<div class=\"secret\">\n");
+                self.blocks(blocks);
+                self.html.push_str("</div>\n");
+            }
+        }
+    }
+
+    fn inlines(&mut self, inlines : &[Inline]) {
+        for inline in inlines {
+            match &inline.kind {
+                InlineKind::Text(text) => self.html.push_str(&escape_html(text)),
+                InlineKind::Emphasis(content) => {
+                    self.html.push_str("<em>");
+                    self.inlines(content);
+                    self.html.push_str("</em>");
+                }
+                InlineKind::Strong(content) => {
+                    self.html.push_str("<strong>");
+                    self.inlines(content);
+                    self.html.push_str("</strong>");
+                }
+                InlineKind::Link{ target, content } => self.link(target, |this| { this.inlines(content) }),
+                InlineKind::WikiLink{ target, text } => self.link(target, |this| { this.html.push_str(&escape_html(text.as_ref().unwrap_or(target))) }),
+                InlineKind::Code(code) => self.html.push_str(&format!("<code>{}</code>", escape_html(code))),
+                InlineKind::Tag(name) => self.html.push_str(&format!("<span class=\"tag\">#{}</span>", escape_html(name))),
+                InlineKind::Secret(content) => {
+                    self.html.push_str("<span class=\"secret\">");
+                    self.inlines(content);
+                    self.html.push_str("</span>");
+                }
+                InlineKind::LineBreak => self.html.push_str("<br>\n"),
+            }
+        }
+    }
+
+    fn link<C>(&mut self, target : &str, content : C)
+        where C : FnOnce(&mut Self)
+    {
+        match (self.link_href)(target) {
+            Some(href) => {
+                self.html.push_str(&format!("<a href=\"{}\">", escape_html(&href)));
+                content(self);
+                self.html.push_str("</a>");
+            }
+            None => {
+                self.html.push_str(&format!("<span class=\"broken-link\" title=\"{}\">", escape_html(target)));
+                content(self);
+                self.html.push_str("</span>");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod html_tests {
+    use super::*;
+    use super::super::{ tokenize, parse_document };
+    fn html(text : &str) -> String {
+        to_html(&parse_document(tokenize(text)), |target| { Some(format!("{}.html", target)) })
+    }
+    #[test]
+    fn blocks_and_inlines() {
+        assert_eq!(html("# Title\nSome *very* **bold**\ntext"), "<h1>Title</h1>\n<p>Some <em>very</em> <strong>bold</strong><br>\ntext</p>\n");
+    }
+    #[test]
+    fn lists_and_quotes() {
+        assert_eq!(html("> quoted\n\n- a\n  3. b"), "<blockquote>\n<p>quoted</p>\n</blockquote>\n<ul>\n<li>a\n<ol start=\"3\">\n<li>b</li>\n</ol>\n</li>\n</ul>\n");
+    }
+    #[test]
+    fn tags_are_marked() {
+        assert_eq!(html("Beware #villain"), "<p>Beware <span class=\"tag\">#villain</span></p>\n");
+    }
+    #[test]
+    fn secrets_are_marked() {
+        assert_eq!(html("!> hidden\n\nThe {{*real*}} king"),
+            "<div class=\"secret\">\n<p>hidden</p>\n</div>\n<p>The <span class=\"secret\"><em>real</em></span> king</p>\n");
+    }
+    #[test]
+    fn text_and_targets_are_escaped() {
+        assert_eq!(html("<b> & [x](\"q\")"), "<p>&lt;b&gt; &amp; <a href=\"&quot;q&quot;.html\">x</a></p>\n");
+        assert_eq!(html("`<i>`"), "<p><code>&lt;i&gt;</code></p>\n");
+        assert_eq!(html("[[A&B]] [[C|<c>]]"), "<p><a href=\"A&amp;B.html\">A&amp;B</a> <a href=\"C.html\">&lt;c&gt;</a></p>\n");
+    }
+    #[test]
+    fn unresolved_links_are_marked() {
+        let html = to_html(&parse_document(tokenize("[gone](Gone)")), |_| { None });
+        assert_eq!(html, "<p><span class=\"broken-link\" title=\"Gone\">gone</span></p>\n");
+    }
+}
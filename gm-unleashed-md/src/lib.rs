@@ -1,10 +1,20 @@
 use std::iter::Peekable;
 use itertools::Itertools;
 
+mod document;
+mod html;
+mod normalize;
+
+pub use document::{ Document, Block, BlockKind, Blocks, ListItem, Inline, InlineKind, Inlines, Node, Diagnostic, DiagnosticKind, Diagnostics, parse_document };
+pub use html::{ to_html, escape_html };
+pub use normalize::normalize;
+
 pub type Tokens = Vec<Token>;
 pub type Links = Vec<Link>;
+pub type Tags = Vec<Tag>;
+pub type SourceRange = std::ops::Range<usize>;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Token {
     Text(String),
     Asterisk,
@@ -13,6 +23,147 @@ pub enum Token {
     LinkMiddle,
     CloseRoundBrace,
     LineBreak,
+    Heading(u8),
+    ListItem(ListMarker),
+    Quote,
+    SecretBlock,
+    OpenSecret,
+    CloseSecret,
+    Escape(Escape),
+    Code(CodeSpan),
+    WikiLink(WikiLink),
+    Tag(Tag),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ListMarker {
+    raw : String,
+    indent : usize,
+    number : Option<u32>,
+}
+
+impl ListMarker {
+    pub fn indent(&self) -> usize { self.indent }
+    pub fn number(&self) -> Option<u32> { self.number }
+}
+
+/// A backslash followed by the ASCII punctuation character it keeps from being read as markup.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Escape {
+    raw : String,
+}
+
+impl Escape {
+    pub fn character(&self) -> char { self.raw.chars().nth(1).unwrap() }
+}
+
+/// A backtick-fenced span whose content is never interpreted as markup.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CodeSpan {
+    raw : String,
+    content : String,
+}
+
+impl CodeSpan {
+    pub fn content(&self) -> &str { &self.content }
+}
+
+/// A `[[Target]]` or `[[Target|text]]` link. Neither part is interpreted as markup.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct WikiLink {
+    raw : String,
+    target : String,
+    text : Option<String>,
+}
+
+impl WikiLink {
+    /// Returns `None` when the target or text contain characters the shorthand cannot hold.
+    pub fn new(target : &str, text : Option<&str>) -> Option<Self> {
+        if !is_wiki_link_part(target, false) || !text.is_none_or(|text| { is_wiki_link_part(text, true) }) {
+            return None;
+        }
+        let raw = match text {
+            Some(text) => format!("{}{}{}{}{}", WIKI_LINK_OPEN, target, special_chars::PIPE, text, WIKI_LINK_CLOSE),
+            None => format!("{}{}{}", WIKI_LINK_OPEN, target, WIKI_LINK_CLOSE),
+        };
+        Some(WikiLink{ raw, target : target.to_string(), text : text.map(str::to_string) })
+    }
+    pub fn target(&self) -> &str { &self.target }
+    /// The display text, which is the target itself unless given after a `|`.
+    pub fn text(&self) -> &str { self.text.as_deref().unwrap_or(&self.target) }
+    pub fn explicit_text(&self) -> Option<&str> { self.text.as_deref() }
+}
+
+/// A `#tag`. It starts after whitespace or punctuation and holds letters, digits, `-` and `_`, at least one of them a letter.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Tag {
+    raw : String,
+}
+
+impl Tag {
+    /// Returns `None` when `name` is not a valid tag name.
+    pub fn new(name : &str) -> Option<Self> {
+        if name.chars().all(is_tag_char) && name.chars().any(char::is_alphabetic) {
+            Some(Tag{ raw : format!("{}{}", special_chars::HASH, name) })
+        } else {
+            None
+        }
+    }
+    /// The name without the leading `#`.
+    pub fn name(&self) -> &str { &self.raw[special_chars::HASH.len_utf8()..] }
+}
+
+fn is_tag_char(ch : char) -> bool {
+    ch.is_alphanumeric() || ch == special_chars::DASH || ch == special_chars::UNDERSCORE
+}
+
+/// Whether a tag may start right after `ch`. A `#` inside a word, after another `#` or in an HTML entity is not a tag.
+fn is_tag_boundary(ch : char) -> bool {
+    !is_tag_char(ch) && ch != special_chars::HASH && ch != special_chars::AMPERSAND
+}
+
+fn is_wiki_link_part(part : &str, allow_pipe : bool) -> bool {
+    !part.is_empty() && part.trim() == part && !part.chars().any(|ch| {
+        ch == special_chars::OPEN_SQUARE_BRACE || ch == special_chars::CLOSE_SQUARE_BRACE || ch == special_chars::LINE_BREAK
+            || (!allow_pipe && ch == special_chars::PIPE)
+    })
+}
+
+pub const MAX_HEADING_LEVEL : u8 = 6;
+const QUOTE_MARKER : &str = "> ";
+const SECRET_BLOCK_MARKER : &str = "!> ";
+const HEADING_MARKERS : [&str; MAX_HEADING_LEVEL as usize] = ["# ", "## ", "### ", "#### ", "##### ", "###### "];
+const WIKI_LINK_OPEN : &str = "[[";
+const WIKI_LINK_CLOSE : &str = "]]";
+
+impl Token {
+    pub fn as_text(&self) -> &str {
+        match self {
+            Token::Text(text) => text,
+            Token::Asterisk => "*",
+            Token::DoubleAsterisk => "**",
+            Token::OpenSquareBrace => "[",
+            Token::LinkMiddle => "](",
+            Token::CloseRoundBrace => ")",
+            Token::LineBreak => "\n",
+            Token::Heading(level) => HEADING_MARKERS[((*level).clamp(1, MAX_HEADING_LEVEL) - 1) as usize],
+            Token::ListItem(marker) => &marker.raw,
+            Token::Quote => QUOTE_MARKER,
+            Token::SecretBlock => SECRET_BLOCK_MARKER,
+            Token::OpenSecret => "{{",
+            Token::CloseSecret => "}}",
+            Token::Escape(escape) => &escape.raw,
+            Token::Code(code) => &code.raw,
+            Token::WikiLink(link) => &link.raw,
+            Token::Tag(tag) => &tag.raw,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SourceToken {
+    pub token : Token,
+    pub range : SourceRange,
 }
 
 pub struct Link {
@@ -32,12 +183,14 @@ pub struct Markdown {
     pub text : Vec<String>,
     pub styles : Vec<StyleSpan>,
     pub breaks : Vec<Break>,
+    pub diagnostics : Diagnostics,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct StyleSpan {
     pub span : Span,
     pub style : Style,
+    pub source : SourceRange,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -51,6 +204,12 @@ pub enum Style {
     Italic,
     Bold,
     Link{ target : String },
+    Heading{ level : u8 },
+    ListItem{ depth : usize, number : Option<u32> },
+    Quote,
+    Code,
+    Tag,
+    Secret,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -64,128 +223,250 @@ pub fn tokenize<S>(text : S) -> Tokens
     _tokenize(text.into().chars().peekable())
 }
 
+pub fn source_tokens(tokens : Tokens) -> Vec<SourceToken> {
+    let mut offset = 0;
+    tokens.into_iter().map(|token| {
+        let start = offset;
+        offset += token.as_text().len();
+        SourceToken{ token, range : start..offset }
+    }).collect()
+}
+
+pub fn token_at(tokens : &[SourceToken], offset : usize) -> Option<&SourceToken> {
+    let idx = tokens.partition_point(|token| { token.range.end <= offset });
+    tokens.get(idx).filter(|token| { token.range.contains(&offset) })
+}
+
+/// The links in the order they appear, found by the same parser that `rewrite_links` and `unlink` use.
 pub fn extract_links<'a, T>(tokens : T) -> Links 
     where T : IntoIterator<Item=&'a Token>
 {
-    _extract_links(tokens.into_iter())
+    parse_document(tokens.into_iter().cloned().collect()).link_targets().into_iter().map(|target| { Link::new(target.to_string()) }).collect()
 }
 
-pub fn parse(tokens : Tokens) -> Markdown {
-    _parse(tokens.into_iter().peekable())
+/// The tags in the order they appear. Where a tag can appear depends on the block structure, so this parses the tokens.
+pub fn extract_tags<'a, T>(tokens : T) -> Tags 
+    where T : IntoIterator<Item=&'a Token>
+{
+    parse_document(tokens.into_iter().cloned().collect()).tags().into_iter().filter_map(Tag::new).collect()
 }
 
-fn  _parse<T>(mut tokens : Peekable<T>) -> Markdown 
-    where T : Iterator<Item=Token>
+pub fn to_text<'a, T>(tokens : T) -> String 
+    where T : IntoIterator<Item=&'a Token>
 {
-    let mut text = Vec::new();
-    let mut styles = Vec::new();
-    let mut breaks = Vec::new();
-    let mut italic_span_start = None;
-    let mut bold_span_start = None;
-    let mut link_span_start = None;
-    let mut link_span_end = 0;
-    let mut inside_link_target = false;
-    let mut link_target = String::new();
-    let mut cur_idx = 0;
-    loop {
-        match tokens.next() {
-            Some(Token::Text(txt)) => {
-                if !inside_link_target {
-                    text.push(txt);
-                    cur_idx += 1;
-                } else {
-                    link_target.push_str(&txt);
-                }
-            }
-            Some(Token::Asterisk) => {
-                if let Some(span_start) = italic_span_start {
-                    styles.push(StyleSpan{
-                        style : Style::Italic,
-                        span : Span { start : span_start, end : cur_idx - 1 },
-                    });
-                    italic_span_start = None;
-                } else {
-                    italic_span_start = Some(cur_idx);
-                }
+    tokens.into_iter().map(|token| { token.as_text() }).collect()
+}
+
+/// Retargets every link for which `rewrite` returns a new target, returning the new text and the number of links
+/// changed. Only the targets are replaced, so the rest of `text` stays exactly as written.
+pub fn rewrite_links<F>(text : &str, mut rewrite : F) -> (String, usize) 
+    where F : FnMut(&str) -> Option<String>
+{
+    let tokens = source_tokens(tokenize(text));
+    let mut edits = Vec::new();
+    let mut escapes = Vec::new();
+    for link in parse_document(tokenize(text)).links() {
+        match &link.kind {
+            InlineKind::Link{ target, .. } => if let Some(new_target) = rewrite(target) {
+                let range = link_middle(&tokens, link).end..link.range.end - Token::CloseRoundBrace.as_text().len();
+                escapes.extend(joined_backtick_escapes(&tokens, &range));
+                edits.push((range, escape_link_target(&new_target)));
             },
-            Some(Token::DoubleAsterisk) => {
-                if let Some(span_start) = bold_span_start {
-                    styles.push(StyleSpan{
-                        style : Style::Bold,
-                        span : Span { start : span_start, end : cur_idx - 1 },
-                    });
-                    bold_span_start = None;
-                } else {
-                    bold_span_start = Some(cur_idx);
-                }                
+            InlineKind::WikiLink{ target, text } => if let Some(new_target) = rewrite(target) {
+                edits.push((link.range.clone(), to_text(&link_tokens(&new_target, text.as_deref()))));
             },
-            Some(Token::OpenSquareBrace) => {
-                if link_span_start.is_none() {
-                    link_span_start = Some(cur_idx);
+            _ => (),
+        }
+    }
+    let count = edits.len();
+    add_escapes(&mut edits, escapes);
+    (splice(text, edits), count)
+}
+
+/// Replaces every link for which `should_unlink` returns true by the source of its text, returning the new text and
+/// the number of links removed. Everything outside the removed links stays exactly as written.
+pub fn unlink<F>(text : &str, mut should_unlink : F) -> (String, usize) 
+    where F : FnMut(&str) -> bool
+{
+    // Removing a link's markers can join brackets from its content into a new link, so repeat until nothing changes.
+    let mut text = text.to_string();
+    let mut total = 0;
+    loop {
+        let tokens = source_tokens(tokenize(text.as_str()));
+        let mut edits = Vec::new();
+        let mut escapes = Vec::new();
+        for link in parse_document(tokenize(text.as_str())).links() {
+            match &link.kind {
+                InlineKind::Link{ target, .. } if should_unlink(target) => {
+                    let content = link.range.start + Token::OpenSquareBrace.as_text().len()..link_middle(&tokens, link).start;
+                    let link_escapes = joined_backtick_escapes(&tokens, &link.range);
+                    let content_escapes = link_escapes.iter().filter(|(range, _)| { range.start >= content.start && range.end <= content.end })
+                        .map(|(range, escaped)| { (range.start - content.start..range.end - content.start, escaped.clone()) })
+                        .collect();
+                    edits.push((link.range.clone(), splice(&text[content], content_escapes)));
+                    escapes.extend(link_escapes);
                 }
-            }
-            Some(Token::LinkMiddle) => {
-                link_span_end = cur_idx - 1;
-                inside_link_target = true;
-            }
-            Some(Token::CloseRoundBrace) => {
-                if inside_link_target {
-                    styles.push(StyleSpan{
-                        style : Style::Link{ target : link_target },
-                        span : Span { start : link_span_start.unwrap(), end : link_span_end },
-                    });    
-                    link_target = String::new();                
+                InlineKind::WikiLink{ target, text : link_text } if should_unlink(target) => {
+                    edits.push((link.range.clone(), escape_text(link_text.as_deref().unwrap_or(target))));
                 }
-                inside_link_target = false;
-            }
-            Some(Token::LineBreak) => {
-                breaks.push(Break{ pos : cur_idx });
-            }
-            None => { 
-                break; 
+                _ => (),
             }
         }
+        if edits.is_empty() {
+            return (text, total);
+        }
+        total += edits.len();
+        add_escapes(&mut edits, escapes);
+        text = splice(&text, edits);
     }
-    Markdown {
-        text,
-        styles,
-        breaks,
+}
+
+/// Cuts every secret out of `text`, leaving the rest exactly as written, for text that is shown as is rather than
+/// rendered.
+pub fn remove_secrets(text : &str) -> String {
+    let secrets = parse_document(tokenize(text)).secret_ranges();
+    splice(text, secrets.into_iter().map(|range| { (range, String::new()) }).collect())
+}
+
+pub fn parse(tokens : Tokens) -> Markdown {
+    parse_document(tokens).to_markdown()
+}
+
+fn push_target_text(target : &mut String, token : &Token) {
+    match token {
+        Token::LineBreak => {}
+        Token::Escape(escape) => target.push(escape.character()),
+        token => target.push_str(token.as_text()),
     }
 }
 
-fn _extract_links<'a, T>(tokens : T) -> Links
-    where T : Iterator<Item=&'a Token>
+/// Escapes the characters that would end or alter a link target, the inverse of `link_target`.
+pub fn escape_link_target(target : &str) -> String {
+    let special = [special_chars::BACKSLASH, special_chars::BACKTICK, special_chars::OPEN_SQUARE_BRACE, special_chars::CLOSE_ROUND_BRACE];
+    escape_chars(target, |ch, _| { special.contains(&ch) })
+}
+
+/// Escapes the characters that would make plain text read as markup inside a line, including any `#` that could start a tag.
+pub fn escape_text(text : &str) -> String {
+    let special = [
+        special_chars::BACKSLASH, special_chars::ASTERISK, special_chars::OPEN_SQUARE_BRACE, special_chars::CLOSE_SQUARE_BRACE,
+        special_chars::BACKTICK, special_chars::OPEN_CURLY_BRACE, special_chars::CLOSE_CURLY_BRACE,
+    ];
+    escape_chars(text, |ch, next| { special.contains(&ch) || (ch == special_chars::HASH && next.is_some_and(is_tag_char)) })
+}
+
+/// The tokens of a link to `target`, using the `[[Target|text]]` shorthand whenever it can hold the target and text.
+pub fn link_tokens(target : &str, text : Option<&str>) -> Tokens {
+    match WikiLink::new(target, text) {
+        Some(link) => vec![Token::WikiLink(link)],
+        None => vec![
+            Token::OpenSquareBrace,
+            Token::Text(escape_text(text.unwrap_or(target))),
+            Token::LinkMiddle,
+            Token::Text(escape_link_target(target)),
+            Token::CloseRoundBrace,
+        ],
+    }
+}
+
+/// Puts a backslash before every character for which `should_escape`, given the character and the one after it, is true.
+fn escape_chars<F>(text : &str, should_escape : F) -> String
+    where F : Fn(char, Option<char>) -> bool
 {
-    #[derive(PartialEq, Eq)]
-    enum State { Initial, LinkOpened, TargetOpened }
-    let mut links = Links::new();
-    let mut state = State::Initial;
-    let mut target = String::new();
-    for token in tokens {
-        if state == State::Initial && token == &Token::OpenSquareBrace {
-            state = State::LinkOpened;
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if should_escape(ch, chars.peek().copied()) {
+            escaped.push(special_chars::BACKSLASH);
         }
-        if state == State::LinkOpened && token == &Token::LinkMiddle {
-            state = State::TargetOpened
-        }
-        if state == State::TargetOpened {
-            if token == &Token::CloseRoundBrace {
-                links.push(Link::new(target));
-                target = String::new();
-                state = State::Initial;
-            } else if let Token::Text(text) = token {
-                target.push_str(text);
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// The `](` token of `link`, which is the first one after its `[` as link text cannot hold another.
+fn link_middle<'a>(tokens : &'a [SourceToken], link : &Inline) -> &'a SourceRange {
+    tokens.iter()
+        .find(|token| { token.token == Token::LinkMiddle && token.range.start > link.range.start })
+        .map(|token| { &token.range })
+        .unwrap()
+}
+
+/// Removing the line breaks in `removed` joins the lines around it, where backticks that were alone on their line could
+/// pair up into a code span. These are the edits that escape the literal backticks on those lines.
+fn joined_backtick_escapes(tokens : &[SourceToken], removed : &SourceRange) -> Vec<(SourceRange, String)> {
+    let is_break = |token : &&SourceToken| { token.token == Token::LineBreak };
+    if !tokens.iter().filter(is_break).any(|token| { token.range.start >= removed.start && token.range.end <= removed.end }) {
+        return Vec::new();
+    }
+    let start = tokens.iter().filter(is_break).rev().find(|token| { token.range.end <= removed.start }).map_or(0, |token| { token.range.end });
+    let end = tokens.iter().filter(is_break).find(|token| { token.range.start >= removed.end }).map_or(usize::MAX, |token| { token.range.start });
+    tokens.iter().filter_map(|token| {
+        match &token.token {
+            Token::Text(text) if text.contains(special_chars::BACKTICK) && token.range.start >= start && token.range.end <= end => {
+                Some((token.range.clone(), escape_chars(text, |ch, _| { ch == special_chars::BACKTICK })))
             }
+            _ => None,
+        }
+    }).collect()
+}
+
+/// Adds each escape that does not overlap an edit already made, which also drops duplicates.
+fn add_escapes(edits : &mut Vec<(SourceRange, String)>, escapes : Vec<(SourceRange, String)>) {
+    for (range, escaped) in escapes {
+        if !edits.iter().any(|(edited, _)| { edited.start < range.end && range.start < edited.end }) {
+            edits.push((range, escaped));
         }
     }
-    links
+}
+
+/// Replaces each range of `text` by its replacement. The ranges must not overlap.
+fn splice(text : &str, mut edits : Vec<(SourceRange, String)>) -> String {
+    let mut text = text.to_string();
+    edits.sort_by_key(|(range, _)| { std::cmp::Reverse(range.start) });
+    for (range, replacement) in edits {
+        text.replace_range(range, &replacement);
+    }
+    text
 }
 
 fn _tokenize<Ch>(mut chars : Peekable<Ch>) -> Tokens 
     where Ch : Iterator<Item=char> + Clone
 {
     let mut tokens = Tokens::new();
+    // A `)` only closes something after `](`, elsewhere it is plain text like `(`.
+    let mut in_link_target = false;
     loop {
+        if matches!(tokens.last(), None | Some(Token::LineBreak) | Some(Token::Quote) | Some(Token::SecretBlock)) {
+            if chars.clone().take(QUOTE_MARKER.len()).eq(QUOTE_MARKER.chars()) {
+                for _ in 0..QUOTE_MARKER.len() {
+                    chars.next();
+                }
+                tokens.push(Token::Quote);
+                continue;
+            }
+            if chars.clone().take(SECRET_BLOCK_MARKER.len()).eq(SECRET_BLOCK_MARKER.chars()) {
+                for _ in 0..SECRET_BLOCK_MARKER.len() {
+                    chars.next();
+                }
+                tokens.push(Token::SecretBlock);
+                continue;
+            }
+            if let Some(level) = heading_level(chars.clone()) {
+                for _ in 0..=level {
+                    chars.next();
+                }
+                tokens.push(Token::Heading(level));
+                continue;
+            }
+            if let Some(marker) = list_marker(chars.clone()) {
+                for _ in 0..marker.raw.chars().count() {
+                    chars.next();
+                }
+                tokens.push(Token::ListItem(marker));
+                continue;
+            }
+        }
         match chars.peek() {
             Some(&special_chars::ASTERISK) => {
                 chars.next();
@@ -196,21 +477,35 @@ fn _tokenize<Ch>(mut chars : Peekable<Ch>) -> Tokens
                     tokens.push(Token::Asterisk);
                 }
             }
-            Some(&special_chars::OPEN_SQUARE_BRACE) => {
-                tokens.push(Token::OpenSquareBrace);
-                chars.next();
+            Some(&special_chars::OPEN_SQUARE_BRACE) => match wiki_link(chars.clone()) {
+                Some(link) => {
+                    for _ in 0..link.raw.chars().count() {
+                        chars.next();
+                    }
+                    tokens.push(Token::WikiLink(link));
+                }
+                None => {
+                    tokens.push(Token::OpenSquareBrace);
+                    chars.next();
+                }
             }
             Some(&special_chars::CLOSE_SQUARE_BRACE) => {
                 chars.next();
                 if chars.peek() == Some(&special_chars::OPEN_ROUND_BRACE) {
                     tokens.push(Token::LinkMiddle);
+                    in_link_target = true;
                     chars.next();
                 } else {
                     tokens.push(Token::Text(special_chars::CLOSE_SQUARE_BRACE.to_string()));
                 }
             }
-            Some(&special_chars::CLOSE_ROUND_BRACE) => {
+            Some(&special_chars::CLOSE_ROUND_BRACE) if in_link_target => {
                 tokens.push(Token::CloseRoundBrace);
+                in_link_target = false;
+                chars.next();
+            }
+            Some(&special_chars::CLOSE_ROUND_BRACE) => {
+                tokens.push(Token::Text(special_chars::CLOSE_ROUND_BRACE.to_string()));
                 chars.next();
             }
             Some(&special_chars::OPEN_ROUND_BRACE) => {
@@ -221,6 +516,57 @@ fn _tokenize<Ch>(mut chars : Peekable<Ch>) -> Tokens
                 tokens.push(Token::LineBreak);
                 chars.next();
             }
+            Some(&brace) if brace == special_chars::OPEN_CURLY_BRACE || brace == special_chars::CLOSE_CURLY_BRACE => {
+                chars.next();
+                if chars.peek() == Some(&brace) {
+                    chars.next();
+                    tokens.push(if brace == special_chars::OPEN_CURLY_BRACE { Token::OpenSecret } else { Token::CloseSecret });
+                } else {
+                    tokens.push(Token::Text(brace.to_string()));
+                }
+            }
+            Some(&special_chars::HASH) => {
+                let after_boundary = tokens.last().and_then(|token| { token.as_text().chars().next_back() }).is_none_or(is_tag_boundary);
+                match tag(chars.clone()).filter(|_| { after_boundary }) {
+                    Some(tag) => {
+                        for _ in 0..tag.raw.chars().count() {
+                            chars.next();
+                        }
+                        tokens.push(Token::Tag(tag));
+                    }
+                    None => {
+                        tokens.push(Token::Text(special_chars::HASH.to_string()));
+                        chars.next();
+                    }
+                }
+            }
+            Some(&special_chars::BACKSLASH) => {
+                chars.next();
+                match chars.peek() {
+                    Some(&ch) if ch.is_ascii_punctuation() => {
+                        chars.next();
+                        tokens.push(Token::Escape(Escape{ raw : format!("{}{}", special_chars::BACKSLASH, ch) }));
+                    }
+                    _ => tokens.push(Token::Text(special_chars::BACKSLASH.to_string())),
+                }
+            }
+            Some(&special_chars::BACKTICK) => {
+                let fence = chars.clone().take_while(|&ch| { ch == special_chars::BACKTICK }).count();
+                match code_span(chars.clone(), fence) {
+                    Some(code) => {
+                        for _ in 0..code.raw.chars().count() {
+                            chars.next();
+                        }
+                        tokens.push(Token::Code(code));
+                    }
+                    None => {
+                        for _ in 0..fence {
+                            chars.next();
+                        }
+                        tokens.push(Token::Text(special_chars::BACKTICK.to_string().repeat(fence)));
+                    }
+                }
+            }
             Some(_) => {
                 let text = chars.take_while_ref(|ch|{ !is_special_char(ch) });
                 let text_as_string : String = text.collect();
@@ -232,13 +578,150 @@ fn _tokenize<Ch>(mut chars : Peekable<Ch>) -> Tokens
     tokens
 }
 
+/// Reads a code span opened by `fence` backticks, closed by a run of exactly as many on the same line.
+fn code_span<Ch>(chars : Ch, fence : usize) -> Option<CodeSpan>
+    where Ch : Iterator<Item=char>
+{
+    let mut chars = chars.skip(fence).peekable();
+    let mut raw = special_chars::BACKTICK.to_string().repeat(fence);
+    let mut content = String::new();
+    loop {
+        match chars.next() {
+            None | Some(special_chars::LINE_BREAK) => return None,
+            Some(special_chars::BACKTICK) => {
+                let mut run = special_chars::BACKTICK.to_string();
+                while let Some(&special_chars::BACKTICK) = chars.peek() {
+                    run.push(special_chars::BACKTICK);
+                    chars.next();
+                }
+                raw.push_str(&run);
+                if run.len() == fence {
+                    break;
+                }
+                content.push_str(&run);
+            }
+            Some(ch) => {
+                raw.push(ch);
+                content.push(ch);
+            }
+        }
+    }
+    // A single space on both sides lets code start or end with a backtick.
+    if content.starts_with(special_chars::SPACE) && content.ends_with(special_chars::SPACE) && content.chars().any(|ch| { ch != special_chars::SPACE }) {
+        content = content[1..content.len() - 1].to_string();
+    }
+    Some(CodeSpan{ raw, content })
+}
+
+/// Reads a `[[Target]]` or `[[Target|text]]` link, which must close on the same line and hold no other brackets.
+fn wiki_link<Ch>(chars : Ch) -> Option<WikiLink>
+    where Ch : Iterator<Item=char>
+{
+    let mut chars = chars.peekable();
+    for expected in WIKI_LINK_OPEN.chars() {
+        if chars.next() != Some(expected) {
+            return None;
+        }
+    }
+    let mut inner = String::new();
+    loop {
+        match chars.next() {
+            None | Some(special_chars::LINE_BREAK) | Some(special_chars::OPEN_SQUARE_BRACE) => return None,
+            Some(special_chars::CLOSE_SQUARE_BRACE) if chars.next() == Some(special_chars::CLOSE_SQUARE_BRACE) => break,
+            Some(special_chars::CLOSE_SQUARE_BRACE) => return None,
+            Some(ch) => inner.push(ch),
+        }
+    }
+    let (target, text) = match inner.split_once(special_chars::PIPE) {
+        Some((target, text)) => (target.trim(), Some(text.trim()).filter(|text| { !text.is_empty() })),
+        None => (inner.trim(), None),
+    };
+    if target.is_empty() {
+        return None;
+    }
+    Some(WikiLink {
+        raw : format!("{}{}{}", WIKI_LINK_OPEN, inner, WIKI_LINK_CLOSE),
+        target : target.to_string(),
+        text : text.map(str::to_string),
+    })
+}
+
+fn tag<Ch>(chars : Ch) -> Option<Tag>
+    where Ch : Iterator<Item=char>
+{
+    let name : String = chars.skip(1).take_while(|&ch| { is_tag_char(ch) }).collect();
+    Tag::new(&name)
+}
+
+fn heading_level<Ch>(mut chars : Ch) -> Option<u8>
+    where Ch : Iterator<Item=char>
+{
+    let mut level = 0;
+    loop {
+        match chars.next() {
+            Some(special_chars::HASH) if level < MAX_HEADING_LEVEL => level += 1,
+            Some(special_chars::SPACE) if level > 0 => return Some(level),
+            _ => return None,
+        }
+    }
+}
+
+fn list_marker<Ch>(chars : Ch) -> Option<ListMarker>
+    where Ch : Iterator<Item=char>
+{
+    const MAX_NUMBER_DIGITS : usize = 9;
+    let mut chars = chars.peekable();
+    let mut raw = String::new();
+    while let Some(&special_chars::SPACE) = chars.peek() {
+        raw.push(special_chars::SPACE);
+        chars.next();
+    }
+    let indent = raw.len();
+    let number = match chars.next() {
+        Some(ch) if ch == special_chars::DASH || ch == special_chars::ASTERISK => {
+            raw.push(ch);
+            None
+        }
+        Some(ch) if ch.is_ascii_digit() => {
+            let mut digits = ch.to_string();
+            while let Some(&digit) = chars.peek().filter(|digit| { digit.is_ascii_digit() }) {
+                digits.push(digit);
+                chars.next();
+            }
+            if digits.len() > MAX_NUMBER_DIGITS || chars.next() != Some(special_chars::PERIOD) {
+                return None;
+            }
+            raw.push_str(&digits);
+            raw.push(special_chars::PERIOD);
+            Some(digits.parse().unwrap())
+        }
+        _ => return None,
+    };
+    if chars.next() != Some(special_chars::SPACE) {
+        return None;
+    }
+    raw.push(special_chars::SPACE);
+    Some(ListMarker{ raw, indent, number })
+}
+
 mod special_chars {
     pub const ASTERISK : char = '*';
+    pub const HASH : char = '#';
+    pub const SPACE : char = ' ';
+    pub const DASH : char = '-';
+    pub const PERIOD : char = '.';
     pub const OPEN_ROUND_BRACE : char = '(';
     pub const CLOSE_ROUND_BRACE : char = ')';
     pub const OPEN_SQUARE_BRACE : char = '[';
     pub const CLOSE_SQUARE_BRACE : char = ']';
     pub const LINE_BREAK : char = '\n';
+    pub const BACKSLASH : char = '\\';
+    pub const BACKTICK : char = '`';
+    pub const PIPE : char = '|';
+    pub const OPEN_CURLY_BRACE : char = '{';
+    pub const CLOSE_CURLY_BRACE : char = '}';
+    pub const UNDERSCORE : char = '_';
+    pub const AMPERSAND : char = '&';
 }
 
 fn is_special_char(&ch : &char) -> bool {
@@ -247,7 +730,12 @@ fn is_special_char(&ch : &char) -> bool {
     ch == special_chars::CLOSE_ROUND_BRACE ||
     ch == special_chars::OPEN_SQUARE_BRACE ||
     ch == special_chars::CLOSE_SQUARE_BRACE ||
-    ch == special_chars::LINE_BREAK
+    ch == special_chars::LINE_BREAK ||
+    ch == special_chars::BACKSLASH ||
+    ch == special_chars::BACKTICK ||
+    ch == special_chars::OPEN_CURLY_BRACE ||
+    ch == special_chars::CLOSE_CURLY_BRACE ||
+    ch == special_chars::HASH
 }
 
 #[cfg(test)]
@@ -269,7 +757,7 @@ mod parser_tests {
         assert_eq!(md.text[1], SAMPLE_TEXT);
         assert_eq!(md.text[2], format!(" {}", SAMPLE_TEXT));
         assert_eq!(md.text.len(), 3);
-        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Italic });
+        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Italic, source : 10..21 });
         assert_eq!(md.styles.len(), 1);
     }
     #[test]
@@ -279,22 +767,29 @@ mod parser_tests {
         assert_eq!(md.text[1], SAMPLE_TEXT);
         assert_eq!(md.text[2], format!(" {}", SAMPLE_TEXT));
         assert_eq!(md.text.len(), 3);
-        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Bold });
+        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Bold, source : 10..23 });
         assert_eq!(md.styles.len(), 1);
     }
     #[test]
     fn bold_and_italic_overlapping_text() {
         let md = parse(tokenize(format!("{}*{}**{}{}*{}**{}", SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT)));
         assert_eq!(md.text.len(), 5);
-        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 2 }, style : Style::Italic });
-        assert_eq!(md.styles[1], StyleSpan{ span : Span{ start : 2, end : 3 }, style : Style::Bold });
+        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 2 }, style : Style::Italic, source : 9..40 });
+        assert_eq!(md.styles[1], StyleSpan{ span : Span{ start : 2, end : 3 }, style : Style::Bold, source : 19..51 });
         assert_eq!(md.styles.len(), 2);
     }
     #[test]
     fn linked_text() {
         let md = parse(tokenize(format!("{}[{}]({}){}", SAMPLE_TEXT, SAMPLE_TEXT, LINK_TARGET, SAMPLE_TEXT)));
         assert_eq!(md.text.len(), 3);
-        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Link{ target: LINK_TARGET.to_string()} });
+        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Link{ target: LINK_TARGET.to_string()}, source : 9..32 });
+        assert_eq!(md.styles.len(), 1);
+    }
+    #[test]
+    fn wiki_linked_text() {
+        let md = parse(tokenize(format!("{}[[{}|{}]]", SAMPLE_TEXT, LINK_TARGET, SAMPLE_TEXT)));
+        assert_eq!(md.text, vec![SAMPLE_TEXT, SAMPLE_TEXT]);
+        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Link{ target: LINK_TARGET.to_string()}, source : 9..33 });
         assert_eq!(md.styles.len(), 1);
     }
     #[test]
@@ -305,12 +800,50 @@ mod parser_tests {
         assert_eq!(md.breaks.len(), 1);
     }
     #[test]
+    fn headings() {
+        let md = parse(tokenize(format!("# {}\n{}\n### *{}*", SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT)));
+        assert_eq!(md.text, vec![SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT]);
+        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 0, end : 0 }, style : Style::Heading{ level : 1 }, source : 0..11 });
+        assert_eq!(md.styles[1], StyleSpan{ span : Span{ start : 2, end : 2 }, style : Style::Italic, source : 26..37 });
+        assert_eq!(md.styles[2], StyleSpan{ span : Span{ start : 2, end : 2 }, style : Style::Heading{ level : 3 }, source : 22..37 });
+        assert_eq!(md.styles.len(), 3);
+        assert_eq!(md.breaks.len(), 2);
+    }
+    #[test]
+    fn empty_heading() {
+        let md = parse(tokenize(format!("## \n{}", SAMPLE_TEXT)));
+        assert_eq!(md.text.len(), 1);
+        assert_eq!(md.styles.len(), 0);
+    }
+    #[test]
+    fn nested_lists() {
+        let md = parse(tokenize("- a\n  1. **b**\n  2. c\n- d\nafter\n  - e"));
+        let items : Vec<&StyleSpan> = md.styles.iter().filter(|style| { style.style != Style::Bold }).collect();
+        assert_eq!(md.text, vec!["a", "b", "c", "d", "after", "e"]);
+        assert_eq!(items[0], &StyleSpan{ span : Span{ start : 0, end : 0 }, style : Style::ListItem{ depth : 0, number : None }, source : 0..21 });
+        assert_eq!(items[1], &StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::ListItem{ depth : 1, number : Some(1) }, source : 4..14 });
+        assert_eq!(items[2], &StyleSpan{ span : Span{ start : 2, end : 2 }, style : Style::ListItem{ depth : 1, number : Some(2) }, source : 15..21 });
+        assert_eq!(items[3], &StyleSpan{ span : Span{ start : 3, end : 3 }, style : Style::ListItem{ depth : 0, number : None }, source : 22..25 });
+        assert_eq!(items[4], &StyleSpan{ span : Span{ start : 5, end : 5 }, style : Style::ListItem{ depth : 0, number : None }, source : 32..37 });
+        assert_eq!(items.len(), 5);
+        assert!(md.styles.contains(&StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Bold, source : 9..14 }));
+    }
+    #[test]
+    fn tokens_know_their_offsets() {
+        let tokens = source_tokens(tokenize("# *a*\nb"));
+        let ranges : Vec<SourceRange> = tokens.iter().map(|token| { token.range.clone() }).collect();
+        assert_eq!(ranges, vec![0..2, 2..3, 3..4, 4..5, 5..6, 6..7]);
+        assert_eq!(token_at(&tokens, 3).map(|token| { &token.token }), Some(&Token::Text("a".to_string())));
+        assert_eq!(token_at(&tokens, 6).map(|token| { &token.token }), Some(&Token::Text("b".to_string())));
+        assert!(token_at(&tokens, 7).is_none());
+    }
+    #[test]
     fn multiple_italic_spans() {
         let md = parse(tokenize(format!("{}*{}*{}*{}*{}*{}*{}", SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT, SAMPLE_TEXT)));
         assert_eq!(md.text.len(), 7);
-        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Italic });
-        assert_eq!(md.styles[1], StyleSpan{ span : Span{ start : 3, end : 3 }, style : Style::Italic });
-        assert_eq!(md.styles[2], StyleSpan{ span : Span{ start : 5, end : 5 }, style : Style::Italic });                     
+        assert_eq!(md.styles[0], StyleSpan{ span : Span{ start : 1, end : 1 }, style : Style::Italic, source : 9..20 });
+        assert_eq!(md.styles[1], StyleSpan{ span : Span{ start : 3, end : 3 }, style : Style::Italic, source : 29..40 });
+        assert_eq!(md.styles[2], StyleSpan{ span : Span{ start : 5, end : 5 }, style : Style::Italic, source : 49..60 });
         assert_eq!(md.styles.len(), 3);
     }
 }
@@ -339,11 +872,141 @@ mod link_extractor_tests {
         assert_eq!(links[1].target(), LINK_TARGET_2);
     }
     #[test]
+    fn escaped_target() {
+        let links = extract_links(&tokenize("[x](Fort (North\\)) and `[y](Code)`"));
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].target(), "Fort (North)");
+        assert_eq!(escape_link_target("Fort (North) [[x]]"), "Fort (North\\) \\[\\[x]]");
+    }
+    #[test]
+    fn wiki_links() {
+        let links = extract_links(&tokenize("[[Goblin King]], [[Fort|the fort]] and [x [[Inside]]](Outer)"));
+        let targets : Vec<&str> = links.iter().map(|link| { link.target() }).collect();
+        assert_eq!(targets, vec!["Goblin King", "Fort", "Outer"]);
+    }
+    #[test]
+    fn wiki_links_after_a_stray_bracket() {
+        let links = extract_links(&tokenize("[optional] see [[Goblin King]]"));
+        let targets : Vec<&str> = links.iter().map(|link| { link.target() }).collect();
+        assert_eq!(targets, vec!["Goblin King"]);
+    }
+    #[test]
     fn no_links_but_brackets() {
         assert_eq!(extract_links(&tokenize(format!("{} ({})", SAMPLE_TEXT, SAMPLE_TEXT))).len(), 0);
     }
 }
 
+#[cfg(test)]
+mod tag_extractor_tests {
+    use super::*;
+    fn names(text : &str) -> Vec<String> {
+        extract_tags(&tokenize(text)).iter().map(|tag| { tag.name().to_string() }).collect()
+    }
+    #[test]
+    fn tags_in_text_and_link_text() {
+        assert_eq!(names("#villain of *#act2*, [the #fort](Fort)"), vec!["villain", "act2", "fort"]);
+    }
+    #[test]
+    fn no_tags_in_targets_or_code() {
+        assert_eq!(names("[a](Page #b) `#c` \\#d #1"), Vec::<String>::new());
+    }
+}
+
+#[cfg(test)]
+mod link_rewriter_tests {
+    use super::*;
+    #[test]
+    fn tokens_round_trip_to_text() {
+        let text = "# Title\nSome *text* with **bold**, [a link](Target) and (braces)]\n- item\n> > quoted\n  2. *sub item*\n###### Next line";
+        assert_eq!(to_text(&tokenize(text)), text);
+    }
+    #[test]
+    fn matching_targets_are_rewritten() {
+        let (text, count) = rewrite_links("[A](Old) and [B](Other) and [C](Old)", |target| {
+            if target == "Old" { Some("New".to_string()) } else { None }
+        });
+        assert_eq!(count, 2);
+        assert_eq!(text, "[A](New) and [B](Other) and [C](New)");
+    }
+    #[test]
+    fn link_text_is_untouched() {
+        let (text, count) = rewrite_links("[Old](Old) Old", |_| { Some("New".to_string()) });
+        assert_eq!(count, 1);
+        assert_eq!(text, "[Old](New) Old");
+    }
+    #[test]
+    fn text_around_links_is_kept_as_written() {
+        let text = "* one   [A](Old)\n\n\n* 2 * 3 [**B**](Old) **";
+        let (text, count) = rewrite_links(text, |_| { Some("New".to_string()) });
+        assert_eq!(count, 2);
+        assert_eq!(text, "* one   [A](New)\n\n\n* 2 * 3 [**B**](New) **");
+        let (text, count) = unlink(&text, |_| { true });
+        assert_eq!(count, 2);
+        assert_eq!(text, "* one   A\n\n\n* 2 * 3 **B** **");
+    }
+    #[test]
+    fn unlinking_keeps_link_text() {
+        let (text, count) = unlink("[The *old* one](Old) and [B](Other)", |target| { target == "Old" });
+        assert_eq!(count, 1);
+        assert_eq!(text, "The *old* one and [B](Other)");
+    }
+    #[test]
+    fn wiki_links_are_rewritten() {
+        let (text, count) = rewrite_links("[[Old]], [[Old|the old one]] and [[Other]]", |target| {
+            match target {
+                "Old" => Some("New".to_string()),
+                "Other" => Some("A|B".to_string()),
+                _ => None,
+            }
+        });
+        assert_eq!(count, 3);
+        assert_eq!(text, "[[New]], [[New|the old one]] and [A|B](A|B)");
+        let (text, count) = unlink("[[*Old*]] and [[Old|the old one]]", |target| { target != "Old" });
+        assert_eq!(count, 1);
+        assert_eq!(text, "\\*Old\\* and [[Old|the old one]]");
+    }
+    #[test]
+    fn wiki_links_after_a_stray_bracket() {
+        let (text, count) = rewrite_links("[optional] see [[Goblin King]]", |_| { Some("Goblin Queen".to_string()) });
+        assert_eq!(count, 1);
+        assert_eq!(text, "[optional] see [[Goblin Queen]]");
+    }
+    #[test]
+    fn unterminated_target_is_kept() {
+        let (text, count) = rewrite_links("[A](Old", |_| { Some("New".to_string()) });
+        assert_eq!(count, 0);
+        assert_eq!(text, "[A](Old");
+    }
+    #[test]
+    fn joined_lines_keep_backticks_literal() {
+        let (text, count) = rewrite_links("`[a](Old\nline) [b](c)`", |target| { if target == "c" { None } else { Some("New".to_string()) } });
+        assert_eq!(count, 1);
+        assert_eq!(text, "\\`[a](New) [b](c)\\`");
+        assert_eq!(parse_document(tokenize(text.as_str())).links().len(), 2);
+        let (text, count) = unlink("[`a](Old\nline) [b](c)`", |target| { target != "c" });
+        assert_eq!(count, 1);
+        assert_eq!(text, "\\`a [b](c)\\`");
+    }
+    #[test]
+    fn unlinking_exposed_links() {
+        let (text, count) = unlink("[[]()]()", |_| { true });
+        assert_eq!(count, 2);
+        assert_eq!(text, "");
+    }
+}
+
+#[cfg(test)]
+mod secret_remover_tests {
+    use super::*;
+    #[test]
+    fn secrets_are_cut_out() {
+        assert_eq!(remove_secrets("Loyal *to the {{false}} king*{{, secretly a spy}}"), "Loyal *to the  king*");
+        assert_eq!(remove_secrets("Public {{secret"), "Public ");
+        assert_eq!(remove_secrets("the duke}} is the thief"), " is the thief");
+        assert_eq!(remove_secrets("Ale\n!> Gold\nWool"), "Ale\n\nWool");
+    }
+}
+
 #[cfg(test)]
 mod tokenizer_tests {
     use super::*;
@@ -398,7 +1061,110 @@ mod tokenizer_tests {
         assert_eq!(tokens[1], Token::Text(SAMPLE_TEXT.to_string()));
         assert_eq!(tokens[2], Token::Text(special_chars::OPEN_ROUND_BRACE.to_string()));
         assert_eq!(tokens[3], Token::Text(SAMPLE_TEXT.to_string()));
-        assert_eq!(tokens[4], Token::CloseRoundBrace);        
+        assert_eq!(tokens[4], Token::Text(special_chars::CLOSE_ROUND_BRACE.to_string()));
+    }
+    #[test]
+    fn round_braces_outside_link_targets_are_text() {
+        let tokens = tokenize("(optional) [a](b) c)");
+        assert_eq!(tokens.iter().filter(|token| { **token == Token::CloseRoundBrace }).count(), 1);
+        assert_eq!(tokens.last(), Some(&Token::Text(special_chars::CLOSE_ROUND_BRACE.to_string())));
+    }
+    #[test]
+    fn heading_text() {
+        let tokens = tokenize(format!("{}\n## {}", SAMPLE_TEXT, SAMPLE_TEXT));
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[1], Token::LineBreak);
+        assert_eq!(tokens[2], Token::Heading(2));
+        assert_eq!(tokens[3], Token::Text(SAMPLE_TEXT.to_string()));
+    }
+    #[test]
+    fn list_item_text() {
+        let tokens = tokenize("* a\n  12. b");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[0], Token::ListItem(ListMarker{ raw : "* ".to_string(), indent : 0, number : None }));
+        assert_eq!(tokens[3], Token::ListItem(ListMarker{ raw : "  12. ".to_string(), indent : 2, number : Some(12) }));
+        assert_eq!(tokens[4], Token::Text("b".to_string()));
+    }
+    #[test]
+    fn lines_that_are_not_list_items() {
+        assert_eq!(tokenize("-no space")[0], Token::Text("-no space".to_string()));
+        assert_eq!(tokenize("1.5 apples")[0], Token::Text("1.5 apples".to_string()));
+        assert_eq!(tokenize("*italic*")[0], Token::Asterisk);
+        assert_eq!(tokenize("a - b").len(), 1);
+    }
+    #[test]
+    fn quoted_text() {
+        let tokens = tokenize("> ## Quoted\n>not quoted");
+        assert_eq!(tokens[0], Token::Quote);
+        assert_eq!(tokens[1], Token::Heading(2));
+        assert_eq!(tokens[4], Token::Text(">not quoted".to_string()));
+    }
+    #[test]
+    fn hashes_that_are_not_headings() {
+        for text in &["####### Seven", "Not # at line start"] {
+            let tokens = tokenize(*text);
+            assert!(tokens.iter().all(|token| { matches!(token, Token::Text(_)) }));
+            assert_eq!(to_text(&tokens), *text);
+        }
+        assert_eq!(tokenize("#NoSpace"), vec![Token::Tag(Tag::new("NoSpace").unwrap())]);
+    }
+    #[test]
+    fn escaped_characters() {
+        let tokens = tokenize("5 \\* 3 \\a");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[1], Token::Escape(Escape{ raw : "\\*".to_string() }));
+        assert_eq!(tokens[3], Token::Text("\\".to_string()));
+        assert_eq!(to_text(&tokens), "5 \\* 3 \\a");
+    }
+    #[test]
+    fn code_spans() {
+        let tokens = tokenize("a `*b*` c `` `x` `` `open");
+        assert_eq!(tokens[1], Token::Code(CodeSpan{ raw : "`*b*`".to_string(), content : "*b*".to_string() }));
+        assert_eq!(tokens[3], Token::Code(CodeSpan{ raw : "`` `x` ``".to_string(), content : "`x`".to_string() }));
+        assert_eq!(tokens[4], Token::Text(" ".to_string()));
+        assert_eq!(tokens[5], Token::Text("`".to_string()));
+    }
+    #[test]
+    fn wiki_links() {
+        let tokens = tokenize("[[Goblin King]] and [[ Fort | the *fort* ]]");
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0], Token::WikiLink(WikiLink::new("Goblin King", None).unwrap()));
+        match &tokens[2] {
+            Token::WikiLink(link) => {
+                assert_eq!(link.target(), "Fort");
+                assert_eq!(link.text(), "the *fort*");
+            }
+            token => panic!("expected a wiki link, got {:?}", token),
+        }
+        assert_eq!(to_text(&tokens), "[[Goblin King]] and [[ Fort | the *fort* ]]");
+    }
+    #[test]
+    fn brackets_that_are_not_wiki_links() {
+        for text in &["[[]]", "[[ |x]]", "[[a\nb]]", "[[a [b]]", "[[a]b]]", "[[open"] {
+            assert!(!tokenize(*text).iter().any(|token| { matches!(token, Token::WikiLink(_)) }), "{:?}", text);
+        }
+    }
+    #[test]
+    fn tags() {
+        assert_eq!(tokenize("#villain, (#act-2_b)"), vec![
+            Token::Tag(Tag::new("villain").unwrap()), Token::Text(", ".to_string()), Token::Text("(".to_string()),
+            Token::Tag(Tag::new("act-2_b").unwrap()), Token::Text(")".to_string()),
+        ]);
+        assert_eq!(Tag::new("act-2_b").unwrap().name(), "act-2_b");
+    }
+    #[test]
+    fn hashes_that_are_not_tags() {
+        for text in &["C#dev", "#1", "##x", "&#39;", "\\#x", "# heading", "`#x`", "#"] {
+            assert!(!tokenize(*text).iter().any(|token| { matches!(token, Token::Tag(_)) }), "{:?}", text);
+        }
+    }
+    #[test]
+    fn secret_markers() {
+        assert_eq!(tokenize("!> a {{b}} {c}\n> !> d"), vec![
+            Token::SecretBlock, Token::Text("a ".to_string()), Token::OpenSecret, Token::Text("b".to_string()), Token::CloseSecret,
+            Token::Text(" ".to_string()), Token::Text("{".to_string()), Token::Text("c".to_string()), Token::Text("}".to_string()),
+            Token::LineBreak, Token::Quote, Token::SecretBlock, Token::Text("d".to_string()),
+        ]);
     }
     #[test]
     fn line_broken_text() {
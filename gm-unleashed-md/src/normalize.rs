@@ -0,0 +1,317 @@
+use super::{
+    Document, Block, BlockKind, ListItem, Inline, InlineKind, Token, Tag, tokenize, to_text, escape_text, escape_link_target, link_tokens,
+    is_tag_char, is_tag_boundary, special_chars,
+};
+
+const LIST_INDENT : usize = 2;
+
+/// Serialises the document as canonical markdown: one blank line between blocks, `-` for bullets and two spaces of
+/// indentation per list level. Parsing the result gives back the same structure.
+pub fn normalize(document : &Document) -> String {
+    block_lines(&document.blocks).join("\n")
+}
+
+fn block_lines(blocks : &[Block]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (idx, block) in blocks.iter().enumerate() {
+        if idx > 0 {
+            lines.push(String::new());
+        }
+        match &block.kind {
+            BlockKind::Paragraph(content) => lines.extend(inline_text(content).split('\n').map(|line| {
+                if starts_with_block_marker(line) { escape_block_marker(line) } else { line.to_string() }
+            })),
+            BlockKind::Heading{ level, content } => lines.push(format!("{}{}", Token::Heading(*level).as_text(), inline_text(content))),
+            BlockKind::List(items) => list_lines(items, 0, &mut lines),
+            BlockKind::Quote(blocks) => nested_lines(Token::Quote, blocks, &mut lines),
+            BlockKind::Secret(blocks) => nested_lines(Token::SecretBlock, blocks, &mut lines),
+        }
+    }
+    lines
+}
+
+fn nested_lines(marker : Token, blocks : &[Block], lines : &mut Vec<String>) {
+    let nested = block_lines(blocks);
+    if nested.is_empty() {
+        lines.push(marker.as_text().to_string());
+    }
+    lines.extend(nested.into_iter().map(|line| { format!("{}{}", marker.as_text(), line) }));
+}
+
+fn starts_with_block_marker(line : &str) -> bool {
+    matches!(tokenize(line).first(), Some(Token::Quote) | Some(Token::SecretBlock) | Some(Token::Heading(_)) | Some(Token::ListItem(_)))
+}
+
+/// Escapes the character that makes paragraph text like `# ` or `1. ` at the start of a line read as a block marker.
+fn escape_block_marker(line : &str) -> String {
+    let (indent, marker) = line.split_at(line.len() - line.trim_start_matches(special_chars::SPACE).len());
+    let (digits, marker) = marker.split_at(marker.len() - marker.trim_start_matches(|ch : char| { ch.is_ascii_digit() }).len());
+    format!("{}{}{}{}", indent, digits, special_chars::BACKSLASH, marker)
+}
+
+fn code_span(code : &str) -> String {
+    let longest_run = code.split(|ch| { ch != special_chars::BACKTICK }).map(str::len).max().unwrap_or(0);
+    let fence = special_chars::BACKTICK.to_string().repeat(longest_run + 1);
+    let padded = code.starts_with(special_chars::BACKTICK) || code.ends_with(special_chars::BACKTICK)
+        || (code.starts_with(special_chars::SPACE) && code.ends_with(special_chars::SPACE) && code.chars().any(|ch| { ch != special_chars::SPACE }));
+    let padding = if padded { " " } else { "" };
+    format!("{}{}{}{}{}", fence, padding, code, padding, fence)
+}
+
+fn list_lines(items : &[ListItem], depth : usize, lines : &mut Vec<String>) {
+    for item in items {
+        let marker = item.number.map_or("-".to_string(), |number| { format!("{}.", number) });
+        lines.push(format!("{}{} {}", " ".repeat(depth * LIST_INDENT), marker, inline_text(&item.content)));
+        // The parser only ever nests lists inside list items.
+        for child in &item.children {
+            if let BlockKind::List(children) = &child.kind {
+                list_lines(children, depth + 1, lines);
+            }
+        }
+    }
+}
+
+fn inline_text(inlines : &[Inline]) -> String {
+    let mut writer = InlineWriter {
+        text : String::new(),
+        open : Vec::new(),
+        next_node : 0,
+        code_end : None,
+        tag_end : None,
+    };
+    writer.inlines(inlines, &mut Vec::new());
+    writer.switch(&[], None);
+    writer.text
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Marker {
+    Emphasis,
+    Strong,
+}
+
+type Path = Vec<(Marker, usize)>;
+type Stack = Vec<(Marker, Option<usize>)>;
+
+/// Writes inlines as the shortest marker runs that make the parser rebuild the same nodes. Between two pieces of text
+/// the tokenizer always reads a run of asterisks as some `**` followed by at most one `*`, so those are the only
+/// runs considered.
+struct InlineWriter {
+    text : String,
+    open : Path,
+    next_node : usize,
+    code_end : Option<usize>,
+    tag_end : Option<usize>,
+}
+
+impl InlineWriter {
+    fn inlines(&mut self, inlines : &[Inline], path : &mut Path) {
+        for inline in inlines {
+            match &inline.kind {
+                InlineKind::Text(text) => self.text(path, text),
+                InlineKind::WikiLink{ target, text } => self.leaf(path, &to_text(&link_tokens(target, text.as_deref()))),
+                InlineKind::Code(code) => self.code(path, code),
+                InlineKind::Tag(name) => self.tag(path, name),
+                InlineKind::LineBreak => self.leaf(path, Token::LineBreak.as_text()),
+                InlineKind::Emphasis(content) => self.styled(Marker::Emphasis, content, path),
+                InlineKind::Strong(content) => self.styled(Marker::Strong, content, path),
+                InlineKind::Secret(content) => {
+                    let secret = format!("{}{}{}", Token::OpenSecret.as_text(), inline_text(content), Token::CloseSecret.as_text());
+                    self.leaf(path, &secret);
+                }
+                InlineKind::Link{ target, content } => {
+                    let link = format!("{}{}{}{}{}", Token::OpenSquareBrace.as_text(), inline_text(content), Token::LinkMiddle.as_text(),
+                        escape_link_target(target), Token::CloseRoundBrace.as_text());
+                    self.leaf(path, &link);
+                }
+            }
+        }
+    }
+
+    fn leaf(&mut self, path : &[(Marker, usize)], text : &str) {
+        self.switch(path, text.chars().next());
+        self.text.push_str(text);
+    }
+
+    fn code(&mut self, path : &[(Marker, usize)], code : &str) {
+        self.switch(path, Some(special_chars::BACKTICK));
+        // Adjacent code spans would read as one fence.
+        if self.code_end == Some(self.text.len()) {
+            self.separate();
+        }
+        self.text.push_str(&code_span(code));
+        self.code_end = Some(self.text.len());
+    }
+
+    fn tag(&mut self, path : &[(Marker, usize)], name : &str) {
+        let tag = match Tag::new(name) {
+            Some(tag) => Token::Tag(tag),
+            None => return self.leaf(path, &escape_text(&format!("{}{}", special_chars::HASH, name))),
+        };
+        self.switch(path, Some(special_chars::HASH));
+        // Straight after a word the `#` would be plain text.
+        if !self.text.chars().next_back().is_none_or(is_tag_boundary) {
+            self.separate();
+        }
+        self.text.push_str(tag.as_text());
+        self.tag_end = Some(self.text.len());
+    }
+
+    fn text(&mut self, path : &[(Marker, usize)], text : &str) {
+        let escaped = escape_text(text);
+        self.switch(path, escaped.chars().next());
+        // Straight after a tag, word characters would read as more of its name.
+        if self.tag_end == Some(self.text.len()) {
+            match text.chars().next() {
+                Some(ch) if ch.is_ascii_punctuation() && is_tag_char(ch) => self.text.push(special_chars::BACKSLASH),
+                Some(ch) if is_tag_char(ch) => self.separate(),
+                _ => {}
+            }
+        }
+        self.text.push_str(&escaped);
+    }
+
+    /// Writes an empty strong span, which keeps the markup on either side apart and is dropped again by the parser.
+    fn separate(&mut self) {
+        self.text.push_str(Token::DoubleAsterisk.as_text());
+        self.text.push_str(Token::DoubleAsterisk.as_text());
+    }
+
+    fn styled(&mut self, marker : Marker, content : &[Inline], path : &mut Path) {
+        // Emphasis inside emphasis of the same kind changes nothing and could not be written without closing the outer one.
+        if path.iter().any(|(open, _)| { *open == marker }) {
+            self.inlines(content, path);
+            return;
+        }
+        path.push((marker, self.next_node));
+        self.next_node += 1;
+        self.inlines(content, path);
+        path.pop();
+    }
+
+    fn switch(&mut self, target : &[(Marker, usize)], next : Option<char>) {
+        let open = &self.open;
+        // A lone `*` followed by a space at the start of a line would read as a bullet.
+        let bullet_risk = (self.text.is_empty() || self.text.ends_with(special_chars::LINE_BREAK)) && next == Some(special_chars::SPACE);
+        let exact = |stack : &Stack| {
+            stack.len() == target.len() && stack.iter().zip(target).all(|(&(marker, node), &(target_marker, target_node))| {
+                marker == target_marker && match node {
+                    Some(node) => node == target_node,
+                    None => open.iter().all(|(_, open_node)| { *open_node != target_node }),
+                }
+            })
+        };
+        let same_order = |stack : &Stack| {
+            stack.iter().map(|(marker, _)| { *marker }).eq(target.iter().map(|(marker, _)| { *marker }))
+        };
+        let same_styles = |stack : &Stack| {
+            stack.len() == target.len() && stack.iter().all(|(marker, _)| { target.iter().any(|(target_marker, _)| { target_marker == marker }) })
+        };
+        // Edited documents can ask for nestings no run produces; fall back to the run that at least gives the same styles.
+        let runs : Vec<(usize, bool)> = (0..=2).flat_map(|strong| { vec![(strong, false), (strong, true)] })
+            .filter(|&run| { !(bullet_risk && run == (0, true)) }).collect();
+        let (strong, emphasis) = [&exact as &dyn Fn(&Stack) -> bool, &same_order, &same_styles].iter().find_map(|matches| {
+            runs.iter().copied().find(|&(strong, emphasis)| { matches(&toggle(open, strong, emphasis)) })
+        }).unwrap_or((0, false));
+        for _ in 0..strong {
+            self.text.push_str(Token::DoubleAsterisk.as_text());
+        }
+        if emphasis {
+            self.text.push_str(Token::Asterisk.as_text());
+        }
+        self.open = target.to_vec();
+    }
+}
+
+/// Mirrors how the parser toggles markers: closing a marker also closes everything opened after it, and those reopen as
+/// new nodes.
+fn toggle(open : &[(Marker, usize)], strong : usize, emphasis : bool) -> Stack {
+    let mut stack : Stack = open.iter().map(|&(marker, node)| { (marker, Some(node)) }).collect();
+    let markers = std::iter::repeat_n(Marker::Strong, strong).chain(Some(Marker::Emphasis).filter(|_| { emphasis }));
+    for marker in markers {
+        match stack.iter().position(|(open, _)| { *open == marker }) {
+            Some(idx) => {
+                let reopened : Stack = stack.drain(idx..).skip(1).map(|(marker, _)| { (marker, None) }).collect();
+                stack.extend(reopened);
+            }
+            None => stack.push((marker, None)),
+        }
+    }
+    stack
+}
+
+#[cfg(test)]
+mod normalize_tests {
+    use super::*;
+    use super::super::{ tokenize, parse_document };
+    fn normalized(text : &str) -> String {
+        normalize(&parse_document(tokenize(text)))
+    }
+    #[test]
+    fn blocks_are_laid_out_canonically() {
+        assert_eq!(normalized("# Title\n\n\n\nsome\ntext\n* a\n    1. b\n    2. c\n* d\n> quoted\n> \n> more"),
+            "# Title\n\nsome\ntext\n\n- a\n  1. b\n  2. c\n- d\n\n> quoted\n> \n> more");
+    }
+    #[test]
+    fn inline_markup_is_kept() {
+        let text = "Some *very **bold** words* and [a *link*](Some Target)";
+        assert_eq!(normalized(text), text);
+    }
+    #[test]
+    fn tags_stay_tags() {
+        assert_eq!(normalized("#villain and **#act2**\n\n\\#not a\\#tag C#"), "#villain and **#act2**\n\n\\#not a\\#tag C#");
+        assert_eq!(normalized("a****#tag"), "a****#tag");
+    }
+    #[test]
+    fn secrets_are_kept() {
+        assert_eq!(normalized("!> # Twist\n!> \n!> a {{*b*}} c\n\n!> "), "!> # Twist\n!> \n!> a {{*b*}} c\n\n!> ");
+        assert_eq!(normalized("\\!> not secret {b}"), "\\!> not secret \\{b\\}");
+    }
+    #[test]
+    fn overlapping_markers_are_written_as_runs() {
+        assert_eq!(normalized("a*b**c*d**"), "a*b**c*d**");
+        assert_eq!(normalized("**x*y***"), "**x*y***");
+    }
+    #[test]
+    fn literal_markers_are_escaped() {
+        assert_eq!(normalized("2 * 3 and [unclosed"), "2 \\* 3 and \\[unclosed");
+        assert_eq!(normalized("a\n****# b\n\\- c\n  12\\. d"), "a\n\\# b\n\\- c\n  12\\. d");
+        assert_eq!(normalized("\\*not\\* `*code*` (aside)"), "\\*not\\* `*code*` (aside)");
+    }
+    #[test]
+    fn code_spans_get_long_enough_fences() {
+        assert_eq!(normalized("``a ` b`` and `` `x` ``"), "``a ` b`` and `` `x` ``");
+        assert_eq!(normalized("`a`****`b` \\``c`"), "`a`****`b` \\``c`");
+    }
+    #[test]
+    fn wiki_links_keep_their_shorthand() {
+        assert_eq!(normalized("[[ Goblin King ]] and [[Fort|*the* fort]]"), "[[Goblin King]] and [[Fort|*the* fort]]");
+        let mut doc = parse_document(tokenize("[[Old]], [[Old|old one]]"));
+        assert_eq!(doc.rewrite_links(|_| { Some("New [2]".to_string()) }), 2);
+        assert_eq!(normalize(&doc), "[New \\[2\\]](New \\[2]), [old one](New \\[2])");
+        assert_eq!(doc.unlink(|_| { true }), 2);
+        assert_eq!(normalize(&doc), "New \\[2\\], old one");
+    }
+    #[test]
+    fn empty_emphasis_is_dropped() {
+        assert_eq!(normalized("a****b\n\n****"), "ab");
+    }
+    #[test]
+    fn link_targets_are_kept_whole() {
+        assert_eq!(normalized("[a](b*c [d]\ne)"), "[a](b*c \\[d]e)");
+    }
+    #[test]
+    fn rewritten_links_survive_markup_in_link_text() {
+        let mut doc = parse_document(tokenize("See [*the* [old] (fort)](Fort) and [x](Other)"));
+        assert_eq!(doc.rewrite_links(|target| { if target == "Fort" { Some("Fort (North)".to_string()) } else { None } }), 1);
+        assert_eq!(normalize(&doc), "See [*the* \\[old\\] (fort)](Fort (North\\)) and [x](Other)");
+        assert_eq!(doc.unlink(|target| { target == "Fort (North)" }), 1);
+        assert_eq!(normalize(&doc), "See *the* \\[old\\] (fort) and [x](Other)");
+    }
+    #[test]
+    fn unlinking_merges_nested_emphasis() {
+        let mut doc = parse_document(tokenize("*a [*b*](T) c*"));
+        doc.unlink(|_| { true });
+        assert_eq!(normalize(&doc), "*a b c*");
+    }
+}
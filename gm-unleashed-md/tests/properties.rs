@@ -0,0 +1,102 @@
+use proptest::prelude::*;
+use gm_unleashed_md::*;
+
+fn markup() -> impl Strategy<Value=String> {
+    prop_oneof![
+        "[-*\\[\\]()#> 1.a\n\\\\`|{}!]{0,64}",
+        "(\\*\\*|\\*|\\[|\\]\\(|\\)|\n|# |- |> |!> |  1\\. |x| |\\\\|`|\\[\\[|\\]\\]|\\||\\{\\{|\\}\\}|#a|&){0,32}",
+        any::<String>(),
+    ]
+}
+
+fn without_ranges(blocks : &[Block]) -> Blocks {
+    blocks.iter().map(|block| {
+        let kind = match &block.kind {
+            BlockKind::Paragraph(content) => BlockKind::Paragraph(inlines_without_ranges(content)),
+            BlockKind::Heading{ level, content } => BlockKind::Heading{ level : *level, content : inlines_without_ranges(content) },
+            BlockKind::List(items) => BlockKind::List(items.iter().map(|item| {
+                ListItem {
+                    number : item.number,
+                    content : inlines_without_ranges(&item.content),
+                    children : without_ranges(&item.children),
+                    range : 0..0,
+                }
+            }).collect()),
+            BlockKind::Quote(blocks) => BlockKind::Quote(without_ranges(blocks)),
+            BlockKind::Secret(blocks) => BlockKind::Secret(without_ranges(blocks)),
+        };
+        Block{ kind, range : 0..0 }
+    }).collect()
+}
+
+fn inlines_without_ranges(inlines : &[Inline]) -> Inlines {
+    inlines.iter().map(|inline| {
+        let kind = match &inline.kind {
+            InlineKind::Emphasis(content) => InlineKind::Emphasis(inlines_without_ranges(content)),
+            InlineKind::Strong(content) => InlineKind::Strong(inlines_without_ranges(content)),
+            InlineKind::Link{ target, content } => InlineKind::Link{ target : target.clone(), content : inlines_without_ranges(content) },
+            InlineKind::Secret(content) => InlineKind::Secret(inlines_without_ranges(content)),
+            kind => kind.clone(),
+        };
+        Inline{ kind, range : 0..0 }
+    }).collect()
+}
+
+proptest! {
+    #[test]
+    fn tokens_round_trip(text in markup()) {
+        prop_assert_eq!(to_text(&tokenize(text.as_str())), text);
+    }
+
+    #[test]
+    fn compatibility_output_is_well_formed(text in markup()) {
+        let md = parse(tokenize(text.as_str()));
+        for style in &md.styles {
+            prop_assert!(style.span.start <= style.span.end);
+            prop_assert!(style.span.end < md.text.len());
+        }
+        let sorted = md.styles.windows(2).all(|pair| { pair[0].span.start <= pair[1].span.start });
+        let breaks_in_bounds = md.breaks.iter().all(|line_break| { line_break.pos <= md.text.len() });
+        prop_assert!(sorted);
+        prop_assert!(breaks_in_bounds);
+    }
+
+    #[test]
+    fn diagnostics_point_into_the_text(text in markup()) {
+        let lines = text.split('\n').count();
+        let doc = parse_document(tokenize(text.as_str()));
+        let in_bounds = doc.diagnostics.iter().all(|diagnostic| { diagnostic.line < lines });
+        prop_assert!(in_bounds);
+    }
+
+    #[test]
+    fn link_rewriting_is_total(text in markup()) {
+        let (kept, count) = rewrite_links(&text, |_| { None });
+        prop_assert_eq!(count, 0);
+        prop_assert_eq!(kept, text.clone());
+        let (retargeted, count) = rewrite_links(&text, |_| { Some("x".to_string()) });
+        let retargeted_doc = parse_document(tokenize(retargeted.as_str()));
+        prop_assert_eq!(retargeted_doc.links().len(), count);
+        let (unlinked, _) = unlink(&text, |_| { true });
+        prop_assert_eq!(parse_document(tokenize(unlinked.as_str())).links().len(), 0);
+    }
+
+    #[test]
+    fn normalized_text_parses_to_the_same_structure(text in markup()) {
+        let doc = parse_document(tokenize(text.as_str()));
+        let normalized = normalize(&doc);
+        let reparsed = parse_document(tokenize(normalized.as_str()));
+        prop_assert_eq!(without_ranges(&reparsed.blocks), without_ranges(&doc.blocks));
+        prop_assert_eq!(normalize(&reparsed), normalized);
+    }
+
+    #[test]
+    fn stripped_documents_have_no_secrets(text in markup()) {
+        let mut doc = parse_document(tokenize(text.as_str()));
+        doc.strip_secrets();
+        let reparsed = parse_document(tokenize(normalize(&doc).as_str()));
+        let mut restripped = reparsed.clone();
+        restripped.strip_secrets();
+        prop_assert_eq!(without_ranges(&restripped.blocks), without_ranges(&reparsed.blocks));
+    }
+}
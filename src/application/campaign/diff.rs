@@ -0,0 +1,122 @@
+/// Above this many word pairs, changed text is shown as removed and added as a whole.
+const MAX_COMPARISONS : usize = 4_000_000;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DiffKind {
+    Same,
+    Removed,
+    Added,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DiffSpan {
+    pub kind : DiffKind,
+    pub text : String,
+}
+
+pub fn word_diff(old : &str, new : &str) -> Vec<DiffSpan> {
+    let old_words = words(old);
+    let new_words = words(new);
+    let prefix = old_words.iter().zip(&new_words).take_while(|(old, new)| { old == new }).count();
+    let suffix = old_words[prefix..].iter().rev().zip(new_words[prefix..].iter().rev()).take_while(|(old, new)| { old == new }).count();
+    let mut spans = Vec::new();
+    push(&mut spans, DiffKind::Same, &old_words[..prefix]);
+    changed_middle(&mut spans, &old_words[prefix..old_words.len() - suffix], &new_words[prefix..new_words.len() - suffix]);
+    push(&mut spans, DiffKind::Same, &old_words[old_words.len() - suffix..]);
+    spans
+}
+
+fn words(text : &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (idx, ch) in text.char_indices().skip(1) {
+        let previous = text[..idx].chars().next_back().unwrap();
+        if ch.is_whitespace() != previous.is_whitespace() {
+            words.push(&text[start..idx]);
+            start = idx;
+        }
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+fn changed_middle(spans : &mut Vec<DiffSpan>, old : &[&str], new : &[&str]) {
+    if old.len() * new.len() > MAX_COMPARISONS {
+        push(spans, DiffKind::Removed, old);
+        push(spans, DiffKind::Added, new);
+        return;
+    }
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let width = new.len() + 1;
+    let mut common = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i * width + j] = if old[i] == new[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            push(spans, DiffKind::Same, &old[i..=i]);
+            i += 1;
+            j += 1;
+        } else if common[(i + 1) * width + j] >= common[i * width + j + 1] {
+            push(spans, DiffKind::Removed, &old[i..=i]);
+            i += 1;
+        } else {
+            push(spans, DiffKind::Added, &new[j..=j]);
+            j += 1;
+        }
+    }
+    push(spans, DiffKind::Removed, &old[i..]);
+    push(spans, DiffKind::Added, &new[j..]);
+}
+
+fn push(spans : &mut Vec<DiffSpan>, kind : DiffKind, words : &[&str]) {
+    if words.is_empty() {
+        return;
+    }
+    match spans.last_mut() {
+        Some(last) if last.kind == kind => last.text.push_str(&words.concat()),
+        _ => spans.push(DiffSpan{ kind, text : words.concat() }),
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    fn span(kind : DiffKind, text : &str) -> DiffSpan {
+        DiffSpan{ kind, text : text.to_string() }
+    }
+    fn side(spans : &[DiffSpan], left_out : DiffKind) -> String {
+        spans.iter().filter(|span| { span.kind != left_out }).map(|span| { span.text.as_str() }).collect()
+    }
+    #[test]
+    fn changed_words_are_marked() {
+        assert_eq!(word_diff("The old fort burned", "The new fort burned down"), vec![
+            span(DiffKind::Same, "The "),
+            span(DiffKind::Removed, "old"),
+            span(DiffKind::Added, "new"),
+            span(DiffKind::Same, " fort burned"),
+            span(DiffKind::Added, " down"),
+        ]);
+        assert_eq!(word_diff("same", "same"), vec![span(DiffKind::Same, "same")]);
+        assert!(word_diff("", "").is_empty());
+    }
+    #[test]
+    fn both_texts_can_be_rebuilt() {
+        let old = "Elara rules\nthe north  with the Duke.";
+        let new = "Queen Elara rules the north\n\nalone, without the Duke.";
+        let spans = word_diff(old, new);
+        assert_eq!(side(&spans, DiffKind::Added), old);
+        assert_eq!(side(&spans, DiffKind::Removed), new);
+        assert_eq!(spans[0], span(DiffKind::Added, "Queen "));
+        assert!(spans.iter().any(|span| { span.kind == DiffKind::Removed && span.text.trim() == "with" }));
+    }
+}
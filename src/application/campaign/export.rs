@@ -0,0 +1,235 @@
+use std::path::Path;
+use std::io::ErrorKind;
+use gm_unleashed_md::{ to_html, escape_html };
+use super::{ Campaign, Entity, EntityId, EntitySort, FieldValue, PlayerView, player_field_value };
+
+pub const INDEX_PAGE : &str = "index.html";
+const PAGE_PREFIX : &str = "entity-";
+const PAGE_EXTENSION : &str = ".html";
+
+const STYLESHEET : &str = "body { font-family: sans-serif; max-width: 48em; margin: 2em auto; padding: 0 1em; }
+nav { margin-bottom: 1em; }
+blockquote { color: #666; border-left: 3px solid #ccc; margin-left: 0; padding-left: 1em; }
+.broken-link { color: #b22; }
+.tag { color: #2a7; }
+.kind { font-style: italic; }
+dt { font-weight: bold; }";
+
+pub fn page_name(id : EntityId) -> String {
+    format!("{}{}{}", PAGE_PREFIX, id, PAGE_EXTENSION)
+}
+
+pub fn export_site<P>(campaign : &Campaign, dir : P) -> Result<usize, ExportError>
+    where P : AsRef<Path>
+{
+    let dir = dir.as_ref();
+    let view = PlayerView::new(campaign);
+    std::fs::create_dir_all(dir).map_err(io_error)?;
+    remove_entity_pages(dir).map_err(io_error)?;
+    std::fs::write(dir.join(INDEX_PAGE), index_page(campaign)).map_err(io_error)?;
+    for entity in campaign.entities().values() {
+        std::fs::write(dir.join(page_name(entity.id())), entity_page(&view, entity)).map_err(io_error)?;
+    }
+    Ok(campaign.entities().len() + 1)
+}
+
+fn remove_entity_pages(dir : &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_page = name.to_str().is_some_and(|name| { name.starts_with(PAGE_PREFIX) && name.ends_with(PAGE_EXTENSION) });
+        if is_page && entry.file_type()?.is_file() {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn index_page(campaign : &Campaign) -> String {
+    let mut body = format!("<h1>{}</h1>\n<ul>\n", escape_html(campaign.name()));
+    for entity in campaign.sorted_entities(EntitySort::Alphabetical) {
+        body.push_str(&format!("<li>{}", entity_link(entity)));
+        if let Some(kind) = entity.kind().and_then(|kind| { campaign.kind(kind) }) {
+            body.push_str(&format!(" <span class=\"kind\">({})</span>", escape_html(kind.name())));
+        }
+        body.push_str("</li>\n");
+    }
+    body.push_str("</ul>\n");
+    page(campaign.name(), &body)
+}
+
+pub fn entity_page(view : &PlayerView, entity : &Entity) -> String {
+    let campaign = view.campaign();
+    let link_href = |target : &str| { campaign.resolve(target).map(page_name) };
+    let mut body = format!("<nav><a href=\"{}\">{}</a></nav>\n<h1>{}</h1>\n", INDEX_PAGE, escape_html(campaign.name()), escape_html(entity.name()));
+    if let Some(kind) = entity.kind().and_then(|kind| { campaign.kind(kind) }) {
+        body.push_str(&format!("<p class=\"kind\">{}</p>\n", escape_html(kind.name())));
+    }
+    let fields = &entity.content().fields;
+    if !fields.is_empty() {
+        body.push_str("<dl>\n");
+        for field in fields {
+            body.push_str(&format!("<dt>{}</dt><dd>{}</dd>\n", escape_html(&field.name), field_html(campaign, &player_field_value(&field.value))));
+        }
+        body.push_str("</dl>\n");
+    }
+    if let Some(document) = view.document(entity.id()) {
+        body.push_str(&to_html(document, link_href));
+    }
+    let linked_from = view.links_to(entity.id());
+    if !linked_from.is_empty() {
+        body.push_str("<h2>Linked from</h2>\n<ul>\n");
+        let mut sources : Vec<&Entity> = linked_from.into_iter().filter_map(|id| { campaign.entity(id) }).collect();
+        sources.sort_by_key(|source| { source.name().to_lowercase() });
+        for source in sources {
+            body.push_str(&format!("<li>{}</li>\n", entity_link(source)));
+        }
+        body.push_str("</ul>\n");
+    }
+    page(entity.name(), &body)
+}
+
+fn field_html(campaign : &Campaign, value : &FieldValue) -> String {
+    match value {
+        FieldValue::Text(text) => escape_html(text),
+        FieldValue::Number(number) => number.to_string(),
+        FieldValue::Boolean(true) => "Yes".to_string(),
+        FieldValue::Boolean(false) => "No".to_string(),
+        FieldValue::EntityReference(target) => match campaign.entity_by_name(target) {
+            Some(entity) => entity_link(entity),
+            None => escape_html(target),
+        },
+        FieldValue::List(items) => items.iter().map(|item| { escape_html(item) }).collect::<Vec<String>>().join(", "),
+    }
+}
+
+fn entity_link(entity : &Entity) -> String {
+    format!("<a href=\"{}\">{}</a>", page_name(entity.id()), escape_html(entity.name()))
+}
+
+fn page(title : &str, body : &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title), STYLESHEET, body
+    )
+}
+
+fn io_error(err : std::io::Error) -> ExportError {
+    ExportError::Io(err.kind())
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum ExportError {
+    Io(ErrorKind),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExportError::Io(kind) => write!(f, "Could not write site: {:?}", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+    use super::super::campaign_tests::text_content;
+    use super::super::{ Field, DanglingLinks };
+    fn sample_campaign() -> Campaign {
+        let mut camp = Campaign::new("Tales & Lore".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        camp.new_entity_of_kind("Fort <North>".to_string(), None).unwrap();
+        let mut content = text_content("Guards [the fort](Fort <North>) and [a ghost](Nobody)");
+        content.fields.push(Field::new("Home", FieldValue::EntityReference("fort <north>".to_string())));
+        camp.update_entity(e, None, content).unwrap();
+        camp
+    }
+    #[test]
+    fn entity_links_become_relative_pages() {
+        let camp = sample_campaign();
+        let elara = camp.entity_by_name("Elara").unwrap();
+        let fort = camp.resolve("Fort <North>").unwrap();
+        let html = entity_page(&PlayerView::new(&camp), elara);
+        let fort_link = format!("<a href=\"{}\">the fort</a>", page_name(fort));
+        assert!(html.contains(&fort_link));
+        assert!(html.contains("<span class=\"broken-link\" title=\"Nobody\">a ghost</span>"));
+        assert!(html.contains(&format!("<dt>Home</dt><dd><a href=\"{}\">Fort &lt;North&gt;</a></dd>", page_name(fort))));
+        assert!(html.contains("<title>Elara</title>"));
+    }
+    #[test]
+    fn pages_list_backlinks() {
+        let camp = sample_campaign();
+        let fort = camp.entity_by_name("Fort <North>").unwrap();
+        let elara = camp.resolve("Elara").unwrap();
+        assert!(entity_page(&PlayerView::new(&camp), fort).contains(&format!("<h2>Linked from</h2>\n<ul>\n<li><a href=\"{}\">Elara</a></li>", page_name(elara))));
+    }
+    #[test]
+    fn secrets_are_left_out() {
+        let mut camp = sample_campaign();
+        let elara = camp.resolve("Elara").unwrap();
+        let fort = camp.resolve("Fort <North>").unwrap();
+        camp.update_entity(fort, None, text_content("Walls{{ with a [[Elara|hidden door]]}}\n\n!> Elara holds the key")).unwrap();
+        let view = PlayerView::new(&camp);
+        let html = entity_page(&view, camp.entity(fort).unwrap());
+        assert!(html.contains("<p>Walls</p>"));
+        assert!(!html.contains("hidden door") && !html.contains("key"));
+        assert!(!entity_page(&view, camp.entity(elara).unwrap()).contains("Linked from"));
+    }
+    #[test]
+    fn unmatched_secret_markers_are_left_out() {
+        let mut camp = sample_campaign();
+        let fort = camp.resolve("Fort <North>").unwrap();
+        for text in &["Public {{secret", "{{the duke\n\nis the thief}}"] {
+            camp.update_entity(fort, None, text_content(text)).unwrap();
+            let html = entity_page(&PlayerView::new(&camp), camp.entity(fort).unwrap());
+            assert!(!html.contains("secret") && !html.contains("duke") && !html.contains("thief"), "{}", html);
+        }
+    }
+    #[test]
+    fn secrets_in_fields_are_left_out() {
+        let mut camp = sample_campaign();
+        let fort = camp.resolve("Fort <North>").unwrap();
+        let mut content = text_content("");
+        content.fields.push(Field::new("Garrison", FieldValue::Text("Twelve guards{{ and a traitor}}".to_string())));
+        content.fields.push(Field::new("Stores", FieldValue::List(vec!["Ale".to_string(), "{{Stolen gold".to_string()])));
+        camp.update_entity(fort, None, content).unwrap();
+        let html = entity_page(&PlayerView::new(&camp), camp.entity(fort).unwrap());
+        assert!(html.contains("<dt>Garrison</dt><dd>Twelve guards</dd>"));
+        assert!(html.contains("<dt>Stores</dt><dd>Ale</dd>"));
+        assert!(!html.contains("traitor") && !html.contains("gold"), "{}", html);
+    }
+    #[test]
+    fn index_lists_every_entity() {
+        let camp = sample_campaign();
+        let html = index_page(&camp);
+        assert!(html.contains("<h1>Tales &amp; Lore</h1>"));
+        let elara = html.find("Elara").unwrap();
+        let fort = html.find("Fort &lt;North&gt;").unwrap();
+        assert!(elara < fort);
+    }
+    #[test]
+    fn site_is_written_to_disk() {
+        let camp = sample_campaign();
+        let dir = std::env::temp_dir().join(format!("gm_unleashed_site_{}", std::process::id()));
+        assert_eq!(export_site(&camp, &dir), Ok(3));
+        assert!(dir.join(INDEX_PAGE).exists());
+        for entity in camp.entities().values() {
+            assert!(dir.join(page_name(entity.id())).exists());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn pages_of_deleted_entities_are_removed() {
+        let mut camp = sample_campaign();
+        let dir = std::env::temp_dir().join(format!("gm_unleashed_stale_site_{}", std::process::id()));
+        export_site(&camp, &dir).unwrap();
+        std::fs::write(dir.join("notes.html"), "kept").unwrap();
+        let fort = camp.resolve("Fort <North>").unwrap();
+        camp.delete_entity(fort, DanglingLinks::Keep).unwrap();
+        assert_eq!(export_site(&camp, &dir), Ok(2));
+        assert!(!dir.join(page_name(fort)).exists());
+        assert!(dir.join("notes.html").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
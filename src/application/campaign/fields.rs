@@ -0,0 +1,68 @@
+use serde::{ Serialize, Deserialize };
+use super::{ FieldType, name_key };
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum FieldValue {
+    Text(String),
+    Number(f64),
+    Boolean(bool),
+    EntityReference(String),
+    List(Vec<String>),
+}
+
+impl FieldValue {
+    pub fn default_for(field_type : FieldType) -> Self {
+        match field_type {
+            FieldType::Text => FieldValue::Text(String::new()),
+            FieldType::Number => FieldValue::Number(0.0),
+            FieldType::Boolean => FieldValue::Boolean(false),
+            FieldType::EntityReference => FieldValue::EntityReference(String::new()),
+            FieldType::List => FieldValue::List(Vec::new()),
+        }
+    }
+
+    pub fn reference(&self) -> Option<&str> {
+        match self {
+            FieldValue::EntityReference(target) if !target.trim().is_empty() => Some(target),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Field {
+    pub name : String,
+    pub value : FieldValue,
+}
+
+impl Field {
+    pub fn new(name : &str, value : FieldValue) -> Self {
+        Field {
+            name : name.to_string(),
+            value,
+        }
+    }
+}
+
+pub type Fields = Vec<Field>;
+
+pub fn reference_targets(fields : &[Field]) -> Vec<String> {
+    fields.iter().filter_map(|field| { field.value.reference() }).map(String::from).collect()
+}
+
+pub fn retarget_references(fields : &mut [Field], old_key : &str, new_name : &str) -> usize {
+    let mut count = 0;
+    for field in fields.iter_mut() {
+        if let FieldValue::EntityReference(target) = &mut field.value {
+            if name_key(target) == old_key {
+                *target = new_name.to_string();
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+pub fn clear_references(fields : &mut [Field], key : &str) -> usize {
+    retarget_references(fields, key, "")
+}
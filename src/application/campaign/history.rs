@@ -0,0 +1,109 @@
+use super::{ EntityId, Entity, KindId, EntityKind };
+
+const MAX_EDITS : usize = 100;
+
+/// The state of one entity or kind before and after an edit, `None` where it did not exist.
+pub(super) struct Change<I, T> {
+    pub(super) id : I,
+    pub(super) before : Option<T>,
+    pub(super) after : Option<T>,
+}
+
+impl<I, T> Change<I, T> {
+    fn reversed(self) -> Self {
+        Change { id : self.id, before : self.after, after : self.before }
+    }
+}
+
+/// One reversible edit of the campaign: everything it touched, as it was before and after.
+pub(super) struct Edit {
+    pub(super) description : String,
+    pub(super) entities : Vec<Change<EntityId, Entity>>,
+    pub(super) kinds : Vec<Change<KindId, EntityKind>>,
+}
+
+impl Edit {
+    pub(super) fn new(description : String) -> Self {
+        Edit {
+            description,
+            entities : Vec::new(),
+            kinds : Vec::new(),
+        }
+    }
+
+    /// The edit that takes the campaign back from its `after` states to its `before` states.
+    fn reversed(self) -> Self {
+        Edit {
+            description : self.description,
+            entities : self.entities.into_iter().map(Change::reversed).collect(),
+            kinds : self.kinds.into_iter().map(Change::reversed).collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entities.iter().all(|change| { same_entity(change.before.as_ref(), change.after.as_ref()) })
+            && self.kinds.iter().all(|change| { change.before.is_none() && change.after.is_none() })
+    }
+
+    pub(super) fn replayed(&self) -> Replayed {
+        Replayed {
+            description : self.description.clone(),
+            renamed : self.entities.iter().filter_map(|change| {
+                match (&change.before, &change.after) {
+                    (Some(before), Some(after)) if before.name != after.name => Some((change.id, before.name.clone(), after.name.clone())),
+                    _ => None,
+                }
+            }).collect(),
+        }
+    }
+}
+
+/// Edits differing only in when they happened change nothing worth undoing.
+fn same_entity(before : Option<&Entity>, after : Option<&Entity>) -> bool {
+    match (before, after) {
+        (Some(before), Some(after)) => before.name == after.name && before.kind == after.kind && before.content == after.content,
+        (before, after) => before.is_none() && after.is_none(),
+    }
+}
+
+/// What undoing or redoing an edit did, for updating anything that shows the changed entities.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Replayed {
+    pub description : String,
+    /// Entities whose name changed, with the name before and after.
+    pub renamed : Vec<(EntityId, String, String)>,
+}
+
+/// The campaign's undo and redo stacks. The redo stack holds undone edits already reversed, so undoing and redoing
+/// both come down to applying the `after` states of an edit.
+pub(super) struct History {
+    undo : Vec<Edit>,
+    redo : Vec<Edit>,
+}
+
+impl History {
+    pub(super) fn new() -> Self {
+        History {
+            undo : Vec::new(),
+            redo : Vec::new(),
+        }
+    }
+
+    /// Records a new edit, which makes the undone edits impossible to redo.
+    pub(super) fn record(&mut self, edit : Edit) {
+        if edit.is_empty() {
+            return;
+        }
+        self.undo.push(edit);
+        if self.undo.len() > MAX_EDITS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// The edit that undoes the latest one. It has to be passed back through `undone` once applied.
+    pub(super) fn next_undo(&mut self) -> Option<Edit> { self.undo.pop().map(Edit::reversed) }
+    pub(super) fn undone(&mut self, edit : Edit) { self.redo.push(edit); }
+    pub(super) fn next_redo(&mut self) -> Option<Edit> { self.redo.pop().map(Edit::reversed) }
+    pub(super) fn redone(&mut self, edit : Edit) { self.undo.push(edit); }
+}
@@ -0,0 +1,93 @@
+use serde::{ Serialize, Deserialize };
+
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct KindId(pub(super) u64);
+
+impl std::fmt::Display for KindId {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum FieldType {
+    Text,
+    Number,
+    Boolean,
+    EntityReference,
+    List,
+}
+
+impl FieldType {
+    pub fn all() -> Vec<FieldType> {
+        use FieldType::*;
+        vec![Text, Number, Boolean, EntityReference, List]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FieldType::Text => "Text",
+            FieldType::Number => "Number",
+            FieldType::Boolean => "Yes/No",
+            FieldType::EntityReference => "Entity",
+            FieldType::List => "List",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FieldSchema {
+    pub name : String,
+    pub field_type : FieldType,
+}
+
+impl FieldSchema {
+    pub fn new(name : &str, field_type : FieldType) -> Self {
+        FieldSchema {
+            name : name.to_string(),
+            field_type,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EntityKind {
+    pub(super) id : KindId,
+    pub(super) name : String,
+    pub(super) color : [f32; 4],
+    pub(super) fields : Vec<FieldSchema>,
+    pub(super) builtin : bool,
+}
+
+impl EntityKind {
+    pub fn id(&self) -> KindId { self.id }
+    pub fn name(&self) -> &str { &self.name }
+    pub fn color(&self) -> [f32; 4] { self.color }
+    pub fn fields(&self) -> &[FieldSchema] { &self.fields }
+}
+
+pub fn builtin_kinds() -> Vec<EntityKind> {
+    use FieldType::*;
+    let kind = |id, name : &str, color, fields| {
+        EntityKind { id : KindId(id), name : name.to_string(), color, fields, builtin : true }
+    };
+    vec![
+        kind(1, "NPC", [0.95, 0.75, 0.3, 1.0], vec![
+            FieldSchema::new("Race", Text),
+            FieldSchema::new("Alignment", Text),
+            FieldSchema::new("HP", Number),
+            FieldSchema::new("Faction", EntityReference),
+        ]),
+        kind(2, "Location", [0.4, 0.85, 0.4, 1.0], vec![
+            FieldSchema::new("Region", EntityReference),
+        ]),
+        kind(3, "Item", [0.6, 0.8, 1.0, 1.0], vec![
+            FieldSchema::new("Value", Number),
+            FieldSchema::new("Magical", Boolean),
+        ]),
+        kind(4, "Faction", [0.85, 0.5, 0.9, 1.0], vec![
+            FieldSchema::new("Leader", EntityReference),
+            FieldSchema::new("Goals", List),
+        ]),
+    ]
+}
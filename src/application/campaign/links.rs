@@ -0,0 +1,133 @@
+use std::collections::{ HashMap, BTreeSet };
+use gm_unleashed_md::{ tokenize, extract_links, rewrite_links, unlink };
+use super::{ EntityId, EntityContent };
+use super::fields::reference_targets;
+
+pub struct LinkIndex {
+    outgoing : HashMap<EntityId, BTreeSet<String>>,
+    incoming : HashMap<String, BTreeSet<EntityId>>,
+}
+
+impl LinkIndex {
+    pub fn new() -> Self {
+        LinkIndex {
+            outgoing : HashMap::new(),
+            incoming : HashMap::new(),
+        }
+    }
+
+    pub fn update<T>(&mut self, source : EntityId, targets : T) 
+        where T : IntoIterator<Item=String>
+    {
+        self.remove(source);
+        let targets : BTreeSet<String> = targets.into_iter().collect();
+        for target in &targets {
+            self.incoming.entry(name_key(target)).or_default().insert(source);
+        }
+        if !targets.is_empty() {
+            self.outgoing.insert(source, targets);
+        }
+    }
+
+    pub fn remove(&mut self, source : EntityId) {
+        if let Some(old_targets) = self.outgoing.remove(&source) {
+            for target in old_targets {
+                let key = name_key(&target);
+                if let Some(sources) = self.incoming.get_mut(&key) {
+                    sources.remove(&source);
+                    if sources.is_empty() {
+                        self.incoming.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn links_from(&self, source : EntityId) -> Vec<&str> {
+        match self.outgoing.get(&source) {
+            Some(targets) => targets.iter().map(|target| { target.as_str() }).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn links_to(&self, target : &str) -> Vec<EntityId> {
+        match self.incoming.get(&name_key(target)) {
+            Some(sources) => sources.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+pub fn link_targets(text : &str) -> Vec<String> {
+    extract_links(&tokenize(text)).iter().map(|link| { link.target().to_string() }).collect()
+}
+
+pub fn content_targets(content : &EntityContent) -> Vec<String> {
+    let mut targets = link_targets(&content.text);
+    targets.extend(reference_targets(&content.fields));
+    targets
+}
+
+pub fn retarget_links(text : &mut String, old_key : &str, new_name : &str) -> usize {
+    let (rewritten, count) = rewrite_links(text, |target| {
+        if name_key(target) == old_key { Some(new_name.to_string()) } else { None }
+    });
+    *text = rewritten;
+    count
+}
+
+pub fn clear_links(text : &mut String, key : &str) -> usize {
+    let (unlinked, count) = unlink(text, |target| { name_key(target) == key });
+    *text = unlinked;
+    count
+}
+
+pub fn name_key(name : &str) -> String {
+    name.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod link_index_tests {
+    use super::*;
+    const A : EntityId = EntityId(1);
+    const B : EntityId = EntityId(2);
+    #[test]
+    fn links_are_indexed_both_ways() {
+        let mut index = LinkIndex::new();
+        index.update(A, vec!["B".to_string(), "C".to_string()]);
+        index.update(B, vec!["C".to_string()]);
+        assert_eq!(index.links_from(A), vec!["B", "C"]);
+        assert_eq!(index.links_to("C"), vec![A, B]);
+        assert_eq!(index.links_to("A").len(), 0);
+    }
+    #[test]
+    fn update_replaces_old_links() {
+        let mut index = LinkIndex::new();
+        index.update(A, vec!["B".to_string()]);
+        index.update(A, vec!["C".to_string()]);
+        assert_eq!(index.links_to("B").len(), 0);
+        assert_eq!(index.links_to("C"), vec![A]);
+    }
+    #[test]
+    fn duplicate_links_are_counted_once() {
+        let mut index = LinkIndex::new();
+        index.update(A, link_targets("[x](B) and [y](B)"));
+        assert_eq!(index.links_from(A), vec!["B"]);
+    }
+    #[test]
+    fn targets_are_case_insensitive() {
+        let mut index = LinkIndex::new();
+        index.update(A, link_targets("[x](Goblin King) and [y](goblin king )"));
+        assert_eq!(index.links_to("GOBLIN KING"), vec![A]);
+    }
+    #[test]
+    fn indexing_and_rewriting_agree() {
+        for text in &["[a](X)", "- [a\n- b](X)", "[a\n\nb](X)", "> [a\nb](X)"] {
+            let indexed = link_targets(text).iter().filter(|target| { name_key(target) == "x" }).count();
+            let mut retargeted = text.to_string();
+            let mut cleared = text.to_string();
+            assert_eq!(retarget_links(&mut retargeted, "x", "Y"), indexed, "{:?}", text);
+            assert_eq!(clear_links(&mut cleared, "x"), indexed, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::cmp::Reverse;
+use aho_corasick::AhoCorasick;
+use gm_unleashed_md::{ Block, BlockKind, Inline, InlineKind, SourceRange, tokenize, parse_document, to_text, link_tokens };
+use super::{ EntityId, Entities };
+
+/// An occurrence of an entity's name in plain text, outside any link or code span.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Mention {
+    pub entity : EntityId,
+    pub name : String,
+    pub range : SourceRange,
+}
+
+/// Searches text for every entity name at once. Names match regardless of case, and only as whole words.
+pub struct MentionMatcher {
+    automaton : Option<AhoCorasick>,
+    entities : Vec<(EntityId, String)>,
+}
+
+impl MentionMatcher {
+    pub fn new(entities : &Entities) -> Self {
+        let entities : Vec<(EntityId, String)> = entities.values().map(|entity| { (entity.id(), entity.name().trim().to_string()) })
+            .filter(|(_, name)| { !name.is_empty() })
+            .collect();
+        let automaton = AhoCorasick::new(entities.iter().map(|(_, name)| { lowercase_with_offsets(name).0 })).ok();
+        MentionMatcher {
+            automaton,
+            entities,
+        }
+    }
+
+    /// Finds the mentions in `text` of every entity except `source`, which is the entity the text belongs to.
+    /// Overlapping names resolve to the leftmost, then the longest.
+    pub fn find(&self, text : &str, source : EntityId) -> Vec<Mention> {
+        let automaton = match &self.automaton {
+            Some(automaton) => automaton,
+            None => return Vec::new(),
+        };
+        let mut ranges = Vec::new();
+        text_ranges(&parse_document(tokenize(text)).blocks, &mut ranges);
+        let mut mentions = Vec::new();
+        for range in ranges {
+            let (lower, offsets) = lowercase_with_offsets(&text[range.clone()]);
+            let at_char_boundary = |idx : usize| { idx == 0 || idx == lower.len() || offsets[idx] != offsets[idx - 1] };
+            let mut candidates : Vec<(SourceRange, usize)> = automaton.find_overlapping_iter(&lower).filter(|found| {
+                at_char_boundary(found.start()) && at_char_boundary(found.end())
+            }).map(|found| {
+                (range.start + offsets[found.start()]..range.start + offsets[found.end()], found.pattern().as_usize())
+            }).filter(|(found, pattern)| {
+                self.entities[*pattern].0 != source && is_whole_word(text, found)
+            }).collect();
+            candidates.sort_by_key(|(found, _)| { (found.start, Reverse(found.end)) });
+            let mut end = range.start;
+            for (found, pattern) in candidates {
+                if found.start >= end {
+                    end = found.end;
+                    let (entity, name) = &self.entities[pattern];
+                    mentions.push(Mention{ entity : *entity, name : name.clone(), range : found });
+                }
+            }
+        }
+        mentions
+    }
+}
+
+/// Turns every mention into a `[[link]]` that keeps the text as written, returning the number of links added.
+pub fn link_mentions(text : &mut String, mentions : &[Mention]) -> usize {
+    let mut ranges : Vec<&SourceRange> = mentions.iter().map(|mention| { &mention.range }).collect();
+    ranges.sort_by_key(|range| { Reverse(range.start) });
+    for range in &ranges {
+        let link = to_text(&link_tokens(&text[(*range).clone()], None));
+        text.replace_range((*range).clone(), &link);
+    }
+    ranges.len()
+}
+
+/// Lowercases `text` like `name_key` does, along with the offset in `text` of the character behind each byte of the
+/// result, plus the length of `text` at the end. Lowercasing can change the length of a character.
+fn lowercase_with_offsets(text : &str) -> (String, Vec<usize>) {
+    let mut lower = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    for (idx, ch) in text.char_indices() {
+        lower.extend(ch.to_lowercase());
+        offsets.resize(lower.len(), idx);
+    }
+    offsets.push(text.len());
+    (lower, offsets)
+}
+
+fn is_whole_word(text : &str, range : &SourceRange) -> bool {
+    let before = text[..range.start].chars().next_back();
+    let after = text[range.end..].chars().next();
+    !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+}
+
+fn text_ranges(blocks : &[Block], ranges : &mut Vec<SourceRange>) {
+    for block in blocks {
+        match &block.kind {
+            BlockKind::Paragraph(content) | BlockKind::Heading{ content, .. } => inline_text_ranges(content, ranges),
+            BlockKind::List(items) => {
+                for item in items {
+                    inline_text_ranges(&item.content, ranges);
+                    text_ranges(&item.children, ranges);
+                }
+            }
+            BlockKind::Quote(blocks) | BlockKind::Secret(blocks) => text_ranges(blocks, ranges),
+        }
+    }
+}
+
+fn inline_text_ranges(inlines : &[Inline], ranges : &mut Vec<SourceRange>) {
+    for inline in inlines {
+        match &inline.kind {
+            InlineKind::Text(_) => ranges.push(inline.range.clone()),
+            InlineKind::Emphasis(content) | InlineKind::Strong(content) | InlineKind::Secret(content) => inline_text_ranges(content, ranges),
+            InlineKind::Link{ .. } | InlineKind::WikiLink{ .. } | InlineKind::Code(_) | InlineKind::Tag(_) | InlineKind::LineBreak => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod mention_tests {
+    use super::*;
+    use super::super::Campaign;
+    fn sample_campaign() -> Campaign {
+        let mut camp = Campaign::new("C".to_string());
+        for name in &["Goblin", "Goblin King", "Elara", "Ox"] {
+            camp.new_entity_of_kind(name.to_string(), None).unwrap();
+        }
+        camp
+    }
+    fn found(camp : &Campaign, text : &str) -> Vec<String> {
+        let source = camp.resolve("Elara").unwrap();
+        MentionMatcher::new(camp.entities()).find(text, source).into_iter().map(|mention| {
+            format!("{}@{}", mention.name, &text[mention.range])
+        }).collect()
+    }
+    #[test]
+    fn longest_names_win() {
+        let camp = sample_campaign();
+        assert_eq!(found(&camp, "The goblin king and a GOBLIN"), vec!["Goblin King@goblin king", "Goblin@GOBLIN"]);
+    }
+    #[test]
+    fn only_whole_words_in_plain_text_match() {
+        let camp = sample_campaign();
+        assert_eq!(found(&camp, "Oxen, [Goblin](Goblin), [[Goblin King]], `Ox` and *Ox*"), vec!["Ox@Ox"]);
+        assert_eq!(found(&camp, "Goblin Kingdom"), vec!["Goblin@Goblin"]);
+    }
+    #[test]
+    fn names_match_regardless_of_unicode_case() {
+        let mut camp = sample_campaign();
+        camp.new_entity_of_kind("Éowyn".to_string(), None).unwrap();
+        camp.new_entity_of_kind("Straße".to_string(), None).unwrap();
+        assert_eq!(found(&camp, "ÉOWYN met éowyn in the STRASSE and the straße"), vec!["Éowyn@ÉOWYN", "Éowyn@éowyn", "Straße@straße"]);
+        assert_eq!(found(&camp, "İox ox"), vec!["Ox@ox"]);
+    }
+    #[test]
+    fn the_source_entity_is_not_mentioned() {
+        let camp = sample_campaign();
+        assert!(found(&camp, "Elara").is_empty());
+    }
+    #[test]
+    fn mentions_become_wiki_links() {
+        let camp = sample_campaign();
+        let mut text = "# The goblin king\nOx and **Goblin**".to_string();
+        let mentions = MentionMatcher::new(camp.entities()).find(&text, camp.resolve("Elara").unwrap());
+        assert_eq!(link_mentions(&mut text, &mentions), 3);
+        assert_eq!(text, "# The [[goblin king]]\n[[Ox]] and **[[Goblin]]**");
+    }
+}
@@ -1,20 +1,83 @@
-use std::collections::{ HashMap };
+use std::collections::{ HashMap, BTreeMap };
+use std::cell::OnceCell;
 
-pub type Entities = HashMap<String, Entity>;
+pub mod persistence;
+pub mod export;
+mod links;
+mod mentions;
+mod player;
+mod tags;
+mod search;
+mod history;
+mod diff;
+mod revisions;
+mod kinds;
+mod fields;
+
+pub use kinds::{ KindId, EntityKind, FieldSchema, FieldType };
+pub use fields::{ Field, FieldValue, Fields };
+
+use links::{ LinkIndex, content_targets };
+pub use links::{ name_key, retarget_links, clear_links };
+use mentions::MentionMatcher;
+use tags::{ TagIndex, text_tags };
+use search::SearchIndex;
+use history::{ History, Edit, Change };
+use revisions::{ RevisionLog, current_timestamp };
+pub use mentions::{ Mention, link_mentions };
+pub use player::{ PlayerView, player_document, player_field_value };
+pub use tags::{ TagQuery, TagQueryError };
+pub use search::{ SearchHit, Snippet };
+pub use history::Replayed;
+pub use diff::{ DiffSpan, DiffKind };
+pub use revisions::{ Revision, RetentionPolicy, revision_diff };
+
+pub type Entities = HashMap<EntityId, Entity>;
+
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct EntityId(u64);
+
+impl std::fmt::Display for EntityId {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 pub struct Campaign {
     name : String,
     entities : Entities,
+    names : HashMap<String, EntityId>,
+    links : LinkIndex,
+    tags : TagIndex,
+    search : SearchIndex,
+    mentions : OnceCell<MentionMatcher>,
+    next_id : u64,
+    edit_counter : u64,
+    kinds : BTreeMap<KindId, EntityKind>,
+    next_kind_id : u64,
+    history : History,
+    revisions : RevisionLog,
+    retention_policy : RetentionPolicy,
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum EntitySort {
+    Alphabetical,
+    RecentlyEdited,
+    CreationOrder,
+}
+
+#[derive(Default, PartialEq, Debug, Clone)]
 pub struct EntityContent {
     pub text : String,
+    pub fields : Fields,
 }
 
 impl EntityContent {
     pub fn new() -> Self {
         EntityContent {
             text : String::new(),
+            fields : Fields::new(),
         }
     }
 }
@@ -24,62 +87,392 @@ impl Campaign {
         Campaign {
             name,
             entities : Entities::new(),
+            names : HashMap::new(),
+            links : LinkIndex::new(),
+            tags : TagIndex::new(),
+            search : SearchIndex::new(),
+            mentions : OnceCell::new(),
+            next_id : 1,
+            edit_counter : 0,
+            kinds : kinds::builtin_kinds().into_iter().map(|kind| { (kind.id, kind) }).collect(),
+            next_kind_id : 100,
+            history : History::new(),
+            revisions : RevisionLog::new(),
+            retention_policy : RetentionPolicy::default(),
+        }
+    }
+
+    pub fn new_kind(&mut self, name : String, color : [f32; 4], fields : Vec<FieldSchema>) -> Result<KindId, NewKindError> {
+        let id = KindId(self.next_kind_id);
+        self.record(format!("Create kind {}", name), Vec::new(), vec![id], |campaign| { campaign.apply_new_kind(name, color, fields) })
+    }
+
+    fn apply_new_kind(&mut self, name : String, color : [f32; 4], fields : Vec<FieldSchema>) -> Result<KindId, NewKindError> {
+        if self.kind_by_name(&name).is_some() {
+            return Err(NewKindError::DuplicateName);
         }
+        let id = KindId(self.next_kind_id);
+        self.insert_kind(EntityKind { id, name, color, fields, builtin : false });
+        Ok(id)
+    }
+
+    pub fn kind(&self, id : KindId) -> Option<&EntityKind> { self.kinds.get(&id) }
+    pub fn kinds(&self) -> impl Iterator<Item=&EntityKind> { self.kinds.values() }
+    pub fn kind_by_name(&self, name : &str) -> Option<&EntityKind> {
+        let key = name_key(name);
+        self.kinds.values().find(|kind| { name_key(&kind.name) == key })
     }
 
-    pub fn new_entity(&mut self, name : String) -> Result<(), NewEntityError> {
-        if self.entities.contains_key(&name) {
+    pub fn new_entity_of_kind(&mut self, name : String, kind : Option<KindId>) -> Result<EntityId, NewEntityError> {
+        let id = EntityId(self.next_id);
+        self.record(format!("Create {}", name), vec![id], Vec::new(), |campaign| { campaign.apply_new_entity(name, kind) })
+    }
+
+    fn apply_new_entity(&mut self, name : String, kind : Option<KindId>) -> Result<EntityId, NewEntityError> {
+        if self.resolve(&name).is_some() {
             Err(NewEntityError::DuplicateName)
+        } else if kind.is_some_and(|kind| { !self.kinds.contains_key(&kind) }) {
+            Err(NewEntityError::NoKind)
         } else {
-            self.entities.insert(name.clone(), Entity::new(name));
-            Ok(())
+            let id = EntityId(self.next_id);
+            let mut entity = Entity::new(id, name);
+            entity.kind = kind;
+            entity.last_edited = self.next_edit();
+            self.insert_entity(entity);
+            Ok(id)
         }
     }
 
-    pub fn update_entity_content(&mut self, name : &str, content : EntityContent) -> Result<(), UpdateEntityError> {
-        match self.entities.get_mut(name) {
+    pub fn update_entity(&mut self, id : EntityId, kind : Option<KindId>, content : EntityContent) -> Result<(), UpdateEntityError> {
+        let description = format!("Edit {}", self.entity_name(id));
+        self.record(description, vec![id], Vec::new(), |campaign| {
+            campaign.apply_kind(id, kind)?;
+            campaign.apply_content(id, content)
+        })
+    }
+
+    fn apply_content(&mut self, id : EntityId, content : EntityContent) -> Result<(), UpdateEntityError> {
+        match self.entities.get_mut(&id) {
             Some(ent) => { 
+                self.links.update(id, content_targets(&content));
+                self.tags.update(id, text_tags(&content.text));
+                self.search.update(id, &ent.name, &content);
                 ent.content = content; 
+                self.edit_counter += 1;
+                ent.last_edited = self.edit_counter;
                 Ok(())
             }
             None => Err(UpdateEntityError::NoEntity)
         }
     }
 
+    fn apply_kind(&mut self, id : EntityId, kind : Option<KindId>) -> Result<(), UpdateEntityError> {
+        if kind.is_some_and(|kind| { !self.kinds.contains_key(&kind) }) {
+            return Err(UpdateEntityError::NoKind);
+        }
+        match self.entities.get_mut(&id) {
+            Some(ent) => {
+                ent.kind = kind;
+                Ok(())
+            }
+            None => Err(UpdateEntityError::NoEntity)
+        }
+    }
+
+    pub fn rename_entity(&mut self, id : EntityId, new_name : String) -> Result<usize, RenameEntityError> {
+        let description = format!("Rename {} to {}", self.entity_name(id), new_name);
+        let mut touched = self.links_to(id);
+        touched.push(id);
+        self.record(description, touched, Vec::new(), |campaign| { campaign.apply_rename(id, new_name) })
+    }
+
+    fn apply_rename(&mut self, id : EntityId, new_name : String) -> Result<usize, RenameEntityError> {
+        let old_name = match self.entities.get(&id) {
+            Some(entity) => entity.name.clone(),
+            None => return Err(RenameEntityError::NoEntity),
+        };
+        if old_name == new_name {
+            return Ok(0);
+        }
+        match self.resolve(&new_name) {
+            Some(other) if other != id => return Err(RenameEntityError::DuplicateName),
+            _ => {}
+        }
+        let old_key = name_key(&old_name);
+        let mut rewritten = 0;
+        for source in self.links.links_to(&old_name) {
+            let entity = self.entities.get_mut(&source).unwrap();
+            rewritten += retarget_links(&mut entity.content.text, &old_key, &new_name);
+            rewritten += fields::retarget_references(&mut entity.content.fields, &old_key, &new_name);
+            self.links.update(source, content_targets(&entity.content));
+            self.search.update(source, &entity.name, &entity.content);
+        }
+        self.names.remove(&old_key);
+        self.names.insert(name_key(&new_name), id);
+        self.mentions = OnceCell::new();
+        let edit = self.next_edit();
+        let entity = self.entities.get_mut(&id).unwrap();
+        entity.name = new_name;
+        entity.last_edited = edit;
+        self.search.update(id, &entity.name, &entity.content);
+        Ok(rewritten)
+    }
+
+    pub fn deletion_preview(&self, id : EntityId) -> Vec<EntityId> {
+        match self.entities.get(&id) {
+            Some(entity) => self.links.links_to(&entity.name).into_iter().filter(|source| { *source != id }).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn delete_entity(&mut self, id : EntityId, dangling_links : DanglingLinks) -> Result<usize, DeleteEntityError> {
+        let description = format!("Delete {}", self.entity_name(id));
+        let mut touched = self.deletion_preview(id);
+        touched.push(id);
+        self.record(description, touched, Vec::new(), |campaign| { campaign.apply_delete(id, dangling_links) })
+    }
+
+    fn apply_delete(&mut self, id : EntityId, dangling_links : DanglingLinks) -> Result<usize, DeleteEntityError> {
+        let entity = match self.entities.remove(&id) {
+            Some(entity) => entity,
+            None => return Err(DeleteEntityError::NoEntity),
+        };
+        let key = name_key(&entity.name);
+        self.names.remove(&key);
+        self.mentions = OnceCell::new();
+        self.links.remove(id);
+        self.tags.remove(id);
+        self.search.remove(id);
+        let mut unlinked = 0;
+        if dangling_links == DanglingLinks::Unlink {
+            for source in self.links.links_to(&entity.name) {
+                let entity = self.entities.get_mut(&source).unwrap();
+                unlinked += clear_links(&mut entity.content.text, &key);
+                unlinked += fields::clear_references(&mut entity.content.fields, &key);
+                self.links.update(source, content_targets(&entity.content));
+                self.search.update(source, &entity.name, &entity.content);
+            }
+        }
+        Ok(unlinked)
+    }
+
+    pub fn sorted_entities(&self, sort : EntitySort) -> Vec<&Entity> {
+        let mut entities : Vec<&Entity> = self.entities.values().collect();
+        match sort {
+            EntitySort::Alphabetical => entities.sort_by(|a, b| { 
+                name_key(&a.name).cmp(&name_key(&b.name)).then_with(|| { a.id.cmp(&b.id) }) 
+            }),
+            EntitySort::RecentlyEdited => entities.sort_by(|a, b| { 
+                b.last_edited.cmp(&a.last_edited).then_with(|| { a.id.cmp(&b.id) }) 
+            }),
+            EntitySort::CreationOrder => entities.sort_by_key(|entity| { entity.id }),
+        }
+        entities
+    }
+
+    pub fn resolve(&self, name : &str) -> Option<EntityId> {
+        self.names.get(&name_key(name)).cloned()
+    }
+
+    pub fn entity(&self, id : EntityId) -> Option<&Entity> { self.entities.get(&id) }
+    pub fn entity_by_name(&self, name : &str) -> Option<&Entity> {
+        self.resolve(name).and_then(|id| { self.entities.get(&id) })
+    }
+
+    pub fn links_to(&self, id : EntityId) -> Vec<EntityId> { 
+        match self.entities.get(&id) {
+            Some(entity) => self.links.links_to(&entity.name),
+            None => Vec::new(),
+        }
+    }
+    pub fn links_from(&self, id : EntityId) -> Vec<&str> { self.links.links_from(id) }
+
+    pub fn all_tags(&self) -> Vec<&str> { self.tags.all_tags() }
+    pub fn query_tags(&self, query : &TagQuery) -> Vec<EntityId> { self.tags.query(query) }
+    pub fn search(&self, query : &str) -> Vec<SearchHit> { self.search.search(query) }
+
+    /// The content of `id` each time it was updated, oldest first.
+    pub fn revisions(&self, id : EntityId) -> &[Revision] { self.revisions.revisions(id) }
+
+    /// Finds names of other entities in `text`, the text of entity `source`, that are not linked yet.
+    pub fn unlinked_mentions(&self, text : &str, source : EntityId) -> Vec<Mention> {
+        self.mentions.get_or_init(|| { MentionMatcher::new(&self.entities) }).find(text, source)
+    }
+
+    /// Takes back the latest edit that has not been undone yet, if any.
+    pub fn undo(&mut self) -> Option<Replayed> {
+        let edit = self.history.next_undo()?;
+        self.replay(&edit);
+        let replayed = edit.replayed();
+        self.history.undone(edit);
+        Some(replayed)
+    }
+
+    /// Makes the latest undone edit again, if nothing was edited since.
+    pub fn redo(&mut self) -> Option<Replayed> {
+        let edit = self.history.next_redo()?;
+        self.replay(&edit);
+        let replayed = edit.replayed();
+        self.history.redone(edit);
+        Some(replayed)
+    }
+
     pub fn entities(&self) -> &Entities { &self.entities }
     pub fn name(&self) -> &str { &self.name }
+
+    fn entity_name(&self, id : EntityId) -> String {
+        self.entities.get(&id).map_or(String::new(), |entity| { entity.name.clone() })
+    }
+
+    /// Runs `apply`, which may only change the given entities and kinds, and records their states before and after
+    /// as one edit on the undo stack. Nothing is recorded when `apply` fails.
+    fn record<T, E, F>(&mut self, description : String, entities : Vec<EntityId>, kinds : Vec<KindId>, apply : F) -> Result<T, E>
+        where F : FnOnce(&mut Self) -> Result<T, E>
+    {
+        let entities_before : Vec<Option<Entity>> = entities.iter().map(|id| { self.entities.get(id).cloned() }).collect();
+        let kinds_before : Vec<Option<EntityKind>> = kinds.iter().map(|id| { self.kinds.get(id).cloned() }).collect();
+        let result = apply(self)?;
+        let mut edit = Edit::new(description);
+        for (id, before) in entities.into_iter().zip(entities_before) {
+            edit.entities.push(Change{ id, before, after : self.entities.get(&id).cloned() });
+        }
+        for (id, before) in kinds.into_iter().zip(kinds_before) {
+            edit.kinds.push(Change{ id, before, after : self.kinds.get(&id).cloned() });
+        }
+        self.record_revisions(&edit);
+        self.history.record(edit);
+        Ok(result)
+    }
+
+    /// Puts every entity and kind touched by `edit` into its `after` state.
+    fn replay(&mut self, edit : &Edit) {
+        for change in &edit.kinds {
+            match &change.after {
+                Some(kind) => self.insert_kind(kind.clone()),
+                None => { self.kinds.remove(&change.id); }
+            }
+        }
+        for change in &edit.entities {
+            if let Some(entity) = self.entities.remove(&change.id) {
+                self.names.remove(&name_key(&entity.name));
+                self.links.remove(change.id);
+                self.tags.remove(change.id);
+                self.search.remove(change.id);
+            }
+        }
+        self.mentions = OnceCell::new();
+        for change in &edit.entities {
+            if let Some(entity) = &change.after {
+                self.insert_entity(entity.clone());
+            }
+        }
+        self.record_revisions(edit);
+    }
+
+    // Renames, deletions and undo change content too, and restoring a revision must not revert those.
+    fn record_revisions(&mut self, edit : &Edit) {
+        let timestamp = current_timestamp();
+        for change in &edit.entities {
+            match (&change.before, &change.after) {
+                (Some(before), Some(after)) if before.content != after.content => {
+                    self.revisions.record(change.id, Revision::new(timestamp, after.content.clone()), &self.retention_policy);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn next_edit(&mut self) -> u64 {
+        self.edit_counter += 1;
+        self.edit_counter
+    }
+
+    fn insert_kind(&mut self, kind : EntityKind) {
+        self.next_kind_id = self.next_kind_id.max(kind.id.0 + 1);
+        self.kinds.insert(kind.id, kind);
+    }
+
+    fn insert_entity(&mut self, entity : Entity) {
+        self.next_id = self.next_id.max(entity.id.0 + 1);
+        self.edit_counter = self.edit_counter.max(entity.last_edited);
+        self.names.insert(name_key(&entity.name), entity.id);
+        self.mentions = OnceCell::new();
+        self.links.update(entity.id, content_targets(&entity.content));
+        self.tags.update(entity.id, text_tags(&entity.content.text));
+        self.search.update(entity.id, &entity.name, &entity.content);
+        self.entities.insert(entity.id, entity);
+    }
 }
 
+#[derive(Clone)]
 pub struct Entity {
+    id : EntityId,
     name : String,
     content : EntityContent,
+    kind : Option<KindId>,
+    last_edited : u64,
 }
 
 impl Entity {
-    pub fn new(name : String) -> Self {
+    pub fn new(id : EntityId, name : String) -> Self {
         Entity {
+            id,
             name,
             content : EntityContent::new(),
+            kind : None,
+            last_edited : 0,
         }
     }
 
+    pub fn id(&self) -> EntityId { self.id }
     pub fn name(&self) -> &str { &self.name }
     pub fn content(&self) -> &EntityContent { &self.content }
+    pub fn kind(&self) -> Option<KindId> { self.kind }
 }
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum NewEntityError {
     DuplicateName,
+    NoKind,
 }
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum UpdateEntityError {
     NoEntity,
+    NoKind,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum NewKindError {
+    DuplicateName,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DanglingLinks {
+    Keep,
+    Unlink,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum DeleteEntityError {
+    NoEntity,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum RenameEntityError {
+    NoEntity,
+    DuplicateName,
 }
 
 #[cfg(test)]
 mod campaign_tests {
     use super::*;
+    pub fn text_content(text : &str) -> EntityContent {
+        EntityContent {
+            text : text.to_string(),
+            fields : Fields::new(),
+        }
+    }
     #[test]
     fn name_is_stored() {
         assert_eq!(Campaign::new("C".to_string()).name(), "C");
@@ -87,25 +480,402 @@ mod campaign_tests {
     #[test]
     fn create_entity_with_name() {
         let mut camp = Campaign::new("C".to_string());
-        camp.new_entity("E".to_string()).unwrap();
-        assert_eq!(camp.entities().get("E").unwrap().name(), "E");
+        let id = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        assert_eq!(camp.entity(id).unwrap().name(), "E");
+        assert_eq!(camp.entity_by_name("E").unwrap().id(), id);
+    }
+    #[test]
+    fn entities_get_distinct_ids() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        assert_ne!(e, f);
     }
     #[test]
     fn cannot_create_entity_with_same_name() {
         let mut camp = Campaign::new("C".to_string());
-        camp.new_entity("E".to_string()).unwrap();
-        assert_eq!(camp.new_entity("E".to_string()), Err(NewEntityError::DuplicateName));
+        camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        assert_eq!(camp.new_entity_of_kind("E".to_string(), None), Err(NewEntityError::DuplicateName));
+        assert_eq!(camp.new_entity_of_kind("e".to_string(), None), Err(NewEntityError::DuplicateName));
+    }
+    #[test]
+    fn names_resolve_case_insensitively() {
+        let mut camp = Campaign::new("C".to_string());
+        let id = camp.new_entity_of_kind("Goblin King".to_string(), None).unwrap();
+        assert_eq!(camp.resolve("goblin king"), Some(id));
+        assert_eq!(camp.resolve("Goblin Queen"), None);
     }
     #[test]
     fn content_is_persisted() {
         let mut camp = Campaign::new("C".to_string());
-        camp.new_entity("E".to_string()).unwrap();
-        camp.update_entity_content("E", EntityContent{ text : "Hello world".to_string() }).unwrap();
-        assert_eq!(camp.entities().get("E").unwrap().content().text, "Hello world");
+        let id = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        camp.update_entity(id, None, text_content("Hello world")).unwrap();
+        assert_eq!(camp.entity(id).unwrap().content().text, "Hello world");
+    }
+    #[test]
+    fn backlinks_follow_content_updates() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("Meets [the F](f)")).unwrap();
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert_eq!(camp.links_from(e), vec!["f"]);
+        camp.update_entity(e, None, text_content("Forgot about F")).unwrap();
+        assert_eq!(camp.links_to(f).len(), 0);
+    }
+    #[test]
+    fn rename_rewrites_links() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("[*F*](F) and [me](E) and [F again](f)")).unwrap();
+        camp.update_entity(f, None, text_content("Not [E](E) itself")).unwrap();
+        assert_eq!(camp.rename_entity(f, "G the Great".to_string()), Ok(2));
+        assert!(camp.resolve("F").is_none());
+        assert_eq!(camp.resolve("G the Great"), Some(f));
+        assert_eq!(camp.entity(f).unwrap().name(), "G the Great");
+        assert_eq!(camp.entity(e).unwrap().content().text, "[*F*](G the Great) and [me](E) and [F again](G the Great)");
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert_eq!(camp.links_to(e), vec![e, f]);
+    }
+    #[test]
+    fn rename_handles_markup_in_link_text() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F [old]".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("* [*the* [keep] (F)](F [old]), [**x**](E)\n\n\n2 * 3 **")).unwrap();
+        assert_eq!(camp.rename_entity(f, "F* (new)".to_string()), Ok(1));
+        assert_eq!(camp.entity(e).unwrap().content().text, "* [*the* [keep] (F)](F* (new\\)), [**x**](E)\n\n\n2 * 3 **");
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert_eq!(camp.delete_entity(f, DanglingLinks::Unlink), Ok(1));
+        assert_eq!(camp.entity(e).unwrap().content().text, "* *the* [keep] (F), [**x**](E)\n\n\n2 * 3 **");
+    }
+    #[test]
+    fn wiki_links_are_tracked_and_rewritten() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("Goblin King".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("[[goblin king]] and [[Goblin King|the king]]")).unwrap();
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert_eq!(camp.rename_entity(f, "Goblin Queen".to_string()), Ok(2));
+        assert_eq!(camp.entity(e).unwrap().content().text, "[[Goblin Queen]] and [[Goblin Queen|the king]]");
+        assert_eq!(camp.delete_entity(f, DanglingLinks::Unlink), Ok(2));
+        assert_eq!(camp.entity(e).unwrap().content().text, "Goblin Queen and the king");
+    }
+    #[test]
+    fn mentions_follow_entity_names() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("Fort".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("The fort and the keep")).unwrap();
+        assert_eq!(camp.unlinked_mentions("The fort and the keep", e).len(), 1);
+        camp.rename_entity(f, "Keep".to_string()).unwrap();
+        let mentions = camp.unlinked_mentions("The fort and the keep", e);
+        assert_eq!(mentions, vec![Mention{ entity : f, name : "Keep".to_string(), range : 17..21 }]);
+        let mut text = "The fort and the keep".to_string();
+        assert_eq!(link_mentions(&mut text, &mentions), 1);
+        camp.update_entity(e, None, text_content(&text)).unwrap();
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert!(camp.unlinked_mentions(&text, e).is_empty());
+    }
+    #[test]
+    fn rename_rewrites_self_links() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("[me](E)")).unwrap();
+        assert_eq!(camp.rename_entity(e, "F".to_string()), Ok(1));
+        assert_eq!(camp.entity(e).unwrap().content().text, "[me](F)");
+        assert_eq!(camp.links_to(e), vec![e]);
+    }
+    #[test]
+    fn rename_changing_only_case() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("goblin king".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(f, None, text_content("[The king](goblin king)")).unwrap();
+        assert_eq!(camp.rename_entity(e, "Goblin King".to_string()), Ok(1));
+        assert_eq!(camp.entity(e).unwrap().name(), "Goblin King");
+        assert_eq!(camp.links_to(e), vec![f]);
+    }
+    #[test]
+    fn cannot_rename_to_existing_name() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        assert_eq!(camp.rename_entity(e, "F".to_string()), Err(RenameEntityError::DuplicateName));
+        camp.delete_entity(f, DanglingLinks::Keep).unwrap();
+        assert_eq!(camp.rename_entity(f, "H".to_string()), Err(RenameEntityError::NoEntity));
+    }
+    #[test]
+    fn deletion_preview_lists_referencing_entities() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("[F](F)")).unwrap();
+        camp.update_entity(f, None, text_content("[me](F)")).unwrap();
+        assert_eq!(camp.deletion_preview(f), vec![e]);
+    }
+    #[test]
+    fn delete_keeping_dangling_links() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("[the F](F)")).unwrap();
+        assert_eq!(camp.delete_entity(f, DanglingLinks::Keep), Ok(0));
+        assert!(camp.entity(f).is_none());
+        assert!(camp.resolve("F").is_none());
+        assert_eq!(camp.entity(e).unwrap().content().text, "[the F](F)");
+        assert_eq!(camp.links_from(e), vec!["F"]);
+    }
+    #[test]
+    fn delete_unlinking_references() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("[the F](F) and [the E](E)")).unwrap();
+        assert_eq!(camp.delete_entity(f, DanglingLinks::Unlink), Ok(1));
+        assert_eq!(camp.entity(e).unwrap().content().text, "the F and [the E](E)");
+        assert_eq!(camp.links_from(e), vec!["E"]);
+        assert_eq!(camp.delete_entity(f, DanglingLinks::Unlink), Err(DeleteEntityError::NoEntity));
+    }
+    #[test]
+    fn ids_are_not_reused_after_deletion() {
+        let mut camp = Campaign::new("C".to_string());
+        camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.delete_entity(f, DanglingLinks::Keep).unwrap();
+        assert_ne!(camp.new_entity_of_kind("G".to_string(), None).unwrap(), f);
+    }
+    #[test]
+    fn sorted_entity_views() {
+        let mut camp = Campaign::new("C".to_string());
+        let b = camp.new_entity_of_kind("b".to_string(), None).unwrap();
+        let c = camp.new_entity_of_kind("C".to_string(), None).unwrap();
+        let a = camp.new_entity_of_kind("A".to_string(), None).unwrap();
+        camp.update_entity(b, None, text_content("edited")).unwrap();
+        let ids = |sort| -> Vec<EntityId> { camp.sorted_entities(sort).iter().map(|entity| { entity.id() }).collect() };
+        assert_eq!(ids(EntitySort::Alphabetical), vec![a, b, c]);
+        assert_eq!(ids(EntitySort::CreationOrder), vec![b, c, a]);
+        assert_eq!(ids(EntitySort::RecentlyEdited), vec![b, a, c]);
+    }
+    #[test]
+    fn builtin_kinds_are_available() {
+        let camp = Campaign::new("C".to_string());
+        for name in &["NPC", "Location", "Item", "Faction"] {
+            assert!(camp.kind_by_name(name).unwrap().builtin);
+        }
+        assert!(camp.kind_by_name("npc").unwrap().fields.iter().any(|field| { field.name == "HP" }));
+    }
+    #[test]
+    fn custom_kinds() {
+        let mut camp = Campaign::new("C".to_string());
+        let deity = camp.new_kind("Deity".to_string(), [1.0, 1.0, 0.0, 1.0], vec![FieldSchema::new("Domain", FieldType::Text)]).unwrap();
+        assert_eq!(camp.kind(deity).unwrap().name(), "Deity");
+        assert!(!camp.kind(deity).unwrap().builtin);
+        assert_eq!(camp.new_kind("deity".to_string(), [1.0; 4], Vec::new()), Err(NewKindError::DuplicateName));
+        assert_eq!(camp.new_kind("NPC".to_string(), [1.0; 4], Vec::new()), Err(NewKindError::DuplicateName));
+    }
+    #[test]
+    fn entities_have_kinds() {
+        let mut camp = Campaign::new("C".to_string());
+        let npc = camp.kind_by_name("NPC").unwrap().id();
+        let location = camp.kind_by_name("Location").unwrap().id();
+        let e = camp.new_entity_of_kind("E".to_string(), Some(npc)).unwrap();
+        assert_eq!(camp.entity(e).unwrap().kind(), Some(npc));
+        camp.update_entity(e, Some(location), text_content("Ruins")).unwrap();
+        assert_eq!(camp.entity(e).unwrap().kind(), Some(location));
+        assert_eq!(camp.update_entity(e, Some(KindId(12345)), text_content("Gone")), Err(UpdateEntityError::NoKind));
+        assert_eq!(camp.entity(e).unwrap().content().text, "Ruins");
+        assert_eq!(camp.new_entity_of_kind("F".to_string(), Some(KindId(12345))), Err(NewEntityError::NoKind));
+    }
+    fn with_ruler(ruler : &str) -> EntityContent {
+        EntityContent {
+            text : String::new(),
+            fields : vec![
+                Field::new("Ruler", FieldValue::EntityReference(ruler.to_string())),
+                Field::new("Population", FieldValue::Number(300.0)),
+            ],
+        }
+    }
+    #[test]
+    fn reference_fields_are_backlinks() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, with_ruler("f")).unwrap();
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert_eq!(camp.links_from(e), vec!["f"]);
+        camp.update_entity(e, None, with_ruler("")).unwrap();
+        assert_eq!(camp.links_to(f).len(), 0);
+    }
+    #[test]
+    fn rename_rewrites_reference_fields() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, with_ruler("f")).unwrap();
+        assert_eq!(camp.rename_entity(f, "G".to_string()), Ok(1));
+        assert_eq!(camp.entity(e).unwrap().content().fields, with_ruler("G").fields);
+        assert_eq!(camp.links_to(f), vec![e]);
+    }
+    #[test]
+    fn delete_unlinking_clears_reference_fields() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, with_ruler("F")).unwrap();
+        assert_eq!(camp.delete_entity(f, DanglingLinks::Unlink), Ok(1));
+        assert_eq!(camp.entity(e).unwrap().content().fields, with_ruler("").fields);
+        assert_eq!(camp.links_from(e).len(), 0);
+    }
+    #[test]
+    fn tags_follow_content_and_deletion() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("#Villain in #act2")).unwrap();
+        camp.update_entity(f, None, text_content("#villain")).unwrap();
+        assert_eq!(camp.all_tags(), vec!["act2", "villain"]);
+        assert_eq!(camp.query_tags(&TagQuery::parse("#villain").unwrap()), vec![e, f]);
+        assert_eq!(camp.query_tags(&TagQuery::parse("#villain #act2").unwrap()), vec![e]);
+        camp.delete_entity(e, DanglingLinks::Keep).unwrap();
+        assert_eq!(camp.all_tags(), vec!["villain"]);
+    }
+    #[test]
+    fn search_follows_edits() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("Fort".to_string(), None).unwrap();
+        camp.update_entity(f, None, text_content("Held by [[Elara]]")).unwrap();
+        let found = |camp : &Campaign, query : &str| -> Vec<EntityId> { camp.search(query).into_iter().map(|hit| { hit.entity }).collect() };
+        assert_eq!(found(&camp, "ela"), vec![e, f]);
+        camp.rename_entity(e, "Queen Mab".to_string()).unwrap();
+        assert_eq!(found(&camp, "mab"), vec![e, f]);
+        assert!(found(&camp, "elara").is_empty());
+        camp.delete_entity(e, DanglingLinks::Unlink).unwrap();
+        assert_eq!(found(&camp, "queen"), vec![f]);
     }
     #[test]
     fn cannot_update_nonexistent_entity() {
         let mut camp = Campaign::new("C".to_string());
-        assert_eq!(camp.update_entity_content("E", EntityContent{ text : "".to_string() }), Err(UpdateEntityError::NoEntity));
+        assert_eq!(camp.update_entity(EntityId(1), None, text_content("")), Err(UpdateEntityError::NoEntity));
     }
-}
\ No newline at end of file
+    fn text_of(camp : &Campaign, id : EntityId) -> &str { &camp.entity(id).unwrap().content().text }
+    #[test]
+    fn content_edits_are_undone_and_redone() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("Queen of the #north")).unwrap();
+        camp.update_entity(e, None, text_content("Oops")).unwrap();
+        assert_eq!(camp.undo().unwrap().description, "Edit Elara");
+        assert_eq!(text_of(&camp, e), "Queen of the #north");
+        assert_eq!(camp.all_tags(), vec!["north"]);
+        camp.redo().unwrap();
+        assert_eq!(text_of(&camp, e), "Oops");
+        assert!(camp.redo().is_none());
+        camp.undo().unwrap();
+        camp.undo().unwrap();
+        assert_eq!(text_of(&camp, e), "");
+        assert_eq!(camp.undo().unwrap().description, "Create Elara");
+        assert!(camp.entity(e).is_none() && camp.resolve("Elara").is_none());
+        assert!(camp.undo().is_none());
+        camp.redo().unwrap();
+        assert_eq!(camp.resolve("Elara"), Some(e));
+    }
+    #[test]
+    fn undoing_a_rename_restores_links() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("Fort".to_string(), None).unwrap();
+        camp.update_entity(f, None, text_content("Held by [[elara|the queen]]")).unwrap();
+        camp.rename_entity(e, "Mab".to_string()).unwrap();
+        let undone = camp.undo().unwrap();
+        assert_eq!(undone.renamed, vec![(e, "Mab".to_string(), "Elara".to_string())]);
+        assert_eq!(text_of(&camp, f), "Held by [[elara|the queen]]");
+        assert_eq!(camp.links_to(e), vec![f]);
+        assert!(camp.resolve("Mab").is_none());
+        assert_eq!(camp.redo().unwrap().renamed, vec![(e, "Elara".to_string(), "Mab".to_string())]);
+        assert_eq!(camp.links_to(e), vec![f]);
+        assert_eq!(camp.search("elara").len(), 0);
+    }
+    #[test]
+    fn undoing_a_deletion_restores_the_entity_and_references() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("Fort".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("Rules from the [[Fort]]")).unwrap();
+        camp.update_entity(f, None, text_content("Held by [[Elara]]")).unwrap();
+        camp.delete_entity(e, DanglingLinks::Unlink).unwrap();
+        camp.undo().unwrap();
+        assert_eq!(text_of(&camp, e), "Rules from the [[Fort]]");
+        assert_eq!(text_of(&camp, f), "Held by [[Elara]]");
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert_eq!(camp.links_to(e), vec![f]);
+    }
+    #[test]
+    fn kind_and_content_are_undone_together() {
+        let mut camp = Campaign::new("C".to_string());
+        let npc = camp.kind_by_name("NPC").unwrap().id();
+        let location = camp.kind_by_name("Location").unwrap().id();
+        let e = camp.new_entity_of_kind("Elara".to_string(), Some(npc)).unwrap();
+        camp.update_entity(e, Some(npc), text_content("Queen")).unwrap();
+        camp.update_entity(e, Some(location), text_content("Ruined palace")).unwrap();
+        camp.undo().unwrap();
+        assert_eq!(camp.entity(e).unwrap().kind(), Some(npc));
+        assert_eq!(text_of(&camp, e), "Queen");
+    }
+    #[test]
+    fn new_edits_clear_the_redo_stack() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        let deity = camp.new_kind("Deity".to_string(), [1.0; 4], Vec::new()).unwrap();
+        camp.update_entity(e, Some(deity), text_content("Goddess")).unwrap();
+        assert_eq!(camp.undo().unwrap().description, "Edit Elara");
+        assert_eq!(camp.undo().unwrap().description, "Create kind Deity");
+        assert!(camp.kind(deity).is_none());
+        camp.update_entity(e, None, text_content("Text")).unwrap();
+        assert!(camp.redo().is_none());
+        assert_eq!(camp.entity(e).unwrap().kind(), None);
+    }
+    #[test]
+    fn content_updates_are_kept_as_revisions() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("Queen")).unwrap();
+        camp.rename_entity(e, "Mab".to_string()).unwrap();
+        camp.update_entity(e, None, text_content("Queen")).unwrap();
+        camp.update_entity(e, None, text_content("Exiled queen")).unwrap();
+        let texts : Vec<&str> = camp.revisions(e).iter().map(|revision| { revision.content().text.as_str() }).collect();
+        assert_eq!(texts, vec!["Queen", "Exiled queen"]);
+        camp.delete_entity(e, DanglingLinks::Keep).unwrap();
+        camp.undo().unwrap();
+        assert_eq!(camp.revisions(e).len(), 2);
+    }
+    #[test]
+    fn rewritten_links_are_kept_as_revisions() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        let f = camp.new_entity_of_kind("Fort".to_string(), None).unwrap();
+        camp.update_entity(f, None, text_content("Held by [[Elara]]")).unwrap();
+        camp.rename_entity(e, "Mab".to_string()).unwrap();
+        let latest = camp.revisions(f).last().unwrap().content().clone();
+        assert_eq!(latest.text, "Held by [[Mab]]");
+        camp.update_entity(f, None, latest).unwrap();
+        assert_eq!(camp.links_to(e), vec![f]);
+        camp.delete_entity(e, DanglingLinks::Unlink).unwrap();
+        assert_eq!(camp.revisions(f).last().unwrap().content().text, "Held by Mab");
+        camp.undo().unwrap();
+        assert_eq!(camp.revisions(f).last().unwrap().content().text, "Held by [[Mab]]");
+        assert_eq!(camp.revisions(f).len(), 4);
+    }
+    #[test]
+    fn failed_and_empty_edits_are_not_recorded() {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("Elara".to_string(), None).unwrap();
+        assert!(camp.new_entity_of_kind("elara".to_string(), None).is_err());
+        assert_eq!(camp.rename_entity(e, "Elara".to_string()), Ok(0));
+        camp.update_entity(e, None, EntityContent::new()).unwrap();
+        assert_eq!(camp.undo().unwrap().description, "Create Elara");
+        assert!(camp.undo().is_none());
+    }
+}
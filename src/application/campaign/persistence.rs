@@ -0,0 +1,508 @@
+use std::path::{ Path, PathBuf };
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::collections::HashSet;
+use serde::{ Serialize, Deserialize };
+use gm_unleashed_md::rewrite_links;
+use super::{ Campaign, Entity, EntityContent, EntityId, EntityKind, KindId, FieldSchema, FieldType, Field, FieldValue, Revision, RetentionPolicy };
+use super::links::name_key;
+
+pub const FORMAT_VERSION : u32 = 5;
+pub const FILE_EXTENSION : &str = "gmu";
+const TEMP_SUFFIX : &str = ".tmp";
+
+#[derive(Serialize, Deserialize)]
+struct VersionHeader {
+    version : u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CampaignFile {
+    version : u32,
+    name : String,
+    #[serde(default)]
+    next_id : Option<u64>,
+    #[serde(default)]
+    kinds : Option<Vec<KindRecord>>,
+    #[serde(default)]
+    retention_policy : Option<RetentionRecord>,
+    entities : Vec<EntityRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RetentionRecord {
+    keep_all_for : u64,
+    then_one_per : u64,
+    max_revisions : usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KindRecord {
+    id : u64,
+    name : String,
+    color : [f32; 4],
+    #[serde(default)]
+    fields : Vec<FieldRecord>,
+    builtin : bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FieldRecord {
+    name : String,
+    field_type : FieldType,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntityRecord {
+    #[serde(default)]
+    id : Option<u64>,
+    name : String,
+    text : String,
+    #[serde(default)]
+    last_edited : u64,
+    #[serde(default)]
+    kind : Option<u64>,
+    #[serde(default)]
+    fields : Vec<FieldValueRecord>,
+    #[serde(default)]
+    revisions : Vec<RevisionRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RevisionRecord {
+    timestamp : u64,
+    text : String,
+    fields : Vec<FieldValueRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FieldValueRecord {
+    name : String,
+    value : FieldValue,
+}
+
+pub fn save<P>(campaign : &Campaign, path : P) -> Result<(), SaveError> 
+    where P : AsRef<Path>
+{
+    let serialized = serialize(campaign)?;
+    let path = path.as_ref();
+    // Writing next to the campaign and renaming over it leaves the old file intact if saving fails halfway.
+    let temp_path = temp_path(path);
+    std::fs::write(&temp_path, serialized).and_then(|()| { std::fs::rename(&temp_path, path) }).map_err(|err| {
+        let _ = std::fs::remove_file(&temp_path);
+        SaveError::Io(err.kind())
+    })
+}
+
+fn temp_path(path : &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsStr::to_os_string).unwrap_or_default();
+    name.push(TEMP_SUFFIX);
+    path.with_file_name(name)
+}
+
+pub fn load<P>(path : P) -> Result<Campaign, LoadError> 
+    where P : AsRef<Path>
+{
+    match std::fs::read_to_string(path) {
+        Ok(data) => deserialize(&data),
+        Err(err) if err.kind() == ErrorKind::NotFound => Err(LoadError::NotFound),
+        Err(err) if err.kind() == ErrorKind::InvalidData => Err(LoadError::Corrupt(err.to_string())),
+        Err(err) => Err(LoadError::Io(err.kind())),
+    }
+}
+
+pub fn serialize(campaign : &Campaign) -> Result<String, SaveError> {
+    // Revisions are saved with their entity, so those of deleted entities are lost once the campaign is closed.
+    let mut entities : Vec<EntityRecord> = campaign.entities().values().map(|entity| {
+        EntityRecord {
+            id : Some(entity.id().0),
+            name : entity.name().to_string(),
+            text : entity.content().text.clone(),
+            last_edited : entity.last_edited,
+            kind : entity.kind().map(|kind| { kind.0 }),
+            fields : field_records(&entity.content().fields),
+            revisions : campaign.revisions(entity.id()).iter().map(|revision| {
+                RevisionRecord {
+                    timestamp : revision.timestamp(),
+                    text : revision.content().text.clone(),
+                    fields : field_records(&revision.content().fields),
+                }
+            }).collect(),
+        }
+    }).collect();
+    let kinds = campaign.kinds().map(|kind| {
+        KindRecord {
+            id : kind.id.0,
+            name : kind.name.clone(),
+            color : kind.color,
+            fields : kind.fields.iter().map(|field| { 
+                FieldRecord { name : field.name.clone(), field_type : field.field_type } 
+            }).collect(),
+            builtin : kind.builtin,
+        }
+    }).collect();
+    entities.sort_by_key(|record| { record.id });
+    let file = CampaignFile {
+        version : FORMAT_VERSION,
+        name : campaign.name().to_string(),
+        next_id : Some(campaign.next_id),
+        kinds : Some(kinds),
+        retention_policy : Some(RetentionRecord {
+            keep_all_for : campaign.retention_policy.keep_all_for,
+            then_one_per : campaign.retention_policy.then_one_per,
+            max_revisions : campaign.retention_policy.max_revisions,
+        }),
+        entities,
+    };
+    serde_json::to_string_pretty(&file).map_err(|err| { SaveError::Serialization(err.to_string()) })
+}
+
+pub fn deserialize(data : &str) -> Result<Campaign, LoadError> {
+    let header : VersionHeader = serde_json::from_str(data).map_err(corrupt)?;
+    if header.version == 0 || header.version > FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(header.version));
+    }
+    let file : CampaignFile = serde_json::from_str(data).map_err(corrupt)?;
+    let mut campaign = Campaign::new(file.name);
+    if let Some(kinds) = file.kinds {
+        campaign.kinds.clear();
+        for record in kinds {
+            if campaign.kind(KindId(record.id)).is_some() {
+                return Err(LoadError::Corrupt(format!("duplicate kind id {}", record.id)));
+            }
+            campaign.insert_kind(EntityKind {
+                id : KindId(record.id),
+                name : record.name,
+                color : record.color,
+                fields : record.fields.into_iter().map(|field| { FieldSchema{ name : field.name, field_type : field.field_type } }).collect(),
+                builtin : record.builtin,
+            });
+        }
+    }
+    if let Some(record) = file.retention_policy {
+        campaign.retention_policy = RetentionPolicy {
+            keep_all_for : record.keep_all_for,
+            then_one_per : record.then_one_per,
+            max_revisions : record.max_revisions,
+        };
+    }
+    let mut legacy_records = Vec::new();
+    for record in file.entities {
+        match record.id {
+            Some(id) => insert_record(&mut campaign, EntityId(id), record)?,
+            None if header.version == 1 => legacy_records.push(record),
+            None => return Err(LoadError::Corrupt(format!("entity '{}' has no id", record.name))),
+        }
+    }
+    disambiguate_legacy_names(&mut legacy_records);
+    for record in legacy_records {
+        let id = EntityId(campaign.next_id);
+        insert_record(&mut campaign, id, record)?;
+    }
+    if let Some(next_id) = file.next_id {
+        campaign.next_id = campaign.next_id.max(next_id);
+    }
+    Ok(campaign)
+}
+
+fn insert_record(campaign : &mut Campaign, id : EntityId, record : EntityRecord) -> Result<(), LoadError> {
+    if campaign.resolve(&record.name).is_some() {
+        return Err(LoadError::Corrupt(format!("duplicate entity name '{}'", record.name)));
+    }
+    if campaign.entity(id).is_some() {
+        return Err(LoadError::Corrupt(format!("duplicate entity id {}", id)));
+    }
+    let kind = record.kind.map(KindId);
+    if let Some(kind) = kind {
+        if campaign.kind(kind).is_none() {
+            return Err(LoadError::Corrupt(format!("entity '{}' has unknown kind {}", record.name, kind)));
+        }
+    }
+    let mut entity = Entity::new(id, record.name);
+    entity.kind = kind;
+    entity.content = entity_content(record.text, record.fields);
+    entity.last_edited = record.last_edited;
+    campaign.insert_entity(entity);
+    campaign.revisions.restore(id, record.revisions.into_iter().map(|revision| {
+        Revision::new(revision.timestamp, entity_content(revision.text, revision.fields))
+    }).collect(), &campaign.retention_policy);
+    Ok(())
+}
+
+/// Version 1 told entity names apart by case, so it could hold both `E` and `e`. The later entity of each such pair
+/// gets a numbered name, and the links that targeted it exactly are retargeted to that name.
+fn disambiguate_legacy_names(records : &mut [EntityRecord]) {
+    let mut taken : HashSet<String> = records.iter().map(|record| { name_key(&record.name) }).collect();
+    let mut seen_keys = HashSet::new();
+    let mut seen_names = HashSet::new();
+    let mut renamed = Vec::new();
+    for record in records.iter_mut() {
+        // Exact duplicates were impossible in version 1 as well, so they are left for `insert_record` to reject.
+        if seen_names.insert(record.name.clone()) && !seen_keys.insert(name_key(&record.name)) {
+            let new_name = (2..).map(|idx| { format!("{} {}", record.name, idx) })
+                .find(|name| { !taken.contains(&name_key(name)) })
+                .unwrap();
+            taken.insert(name_key(&new_name));
+            renamed.push((std::mem::replace(&mut record.name, new_name.clone()), new_name));
+        }
+    }
+    if renamed.is_empty() {
+        return;
+    }
+    for record in records.iter_mut() {
+        record.text = rewrite_links(&record.text, |target| {
+            renamed.iter().find(|(old_name, _)| { old_name == target }).map(|(_, new_name)| { new_name.clone() })
+        }).0;
+    }
+}
+
+fn field_records(fields : &[Field]) -> Vec<FieldValueRecord> {
+    fields.iter().map(|field| { FieldValueRecord { name : field.name.clone(), value : field.value.clone() } }).collect()
+}
+
+fn entity_content(text : String, fields : Vec<FieldValueRecord>) -> EntityContent {
+    EntityContent {
+        text,
+        fields : fields.into_iter().map(|field| { Field{ name : field.name, value : field.value } }).collect(),
+    }
+}
+
+fn corrupt(err : serde_json::Error) -> LoadError {
+    LoadError::Corrupt(err.to_string())
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum SaveError {
+    Io(ErrorKind),
+    Serialization(String),
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum LoadError {
+    NotFound,
+    Io(ErrorKind),
+    Corrupt(String),
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SaveError::Io(kind) => write!(f, "Could not write campaign file: {:?}", kind),
+            SaveError::Serialization(msg) => write!(f, "Could not serialize campaign: {}", msg),
+        }
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::NotFound => write!(f, "Campaign file not found"),
+            LoadError::Io(kind) => write!(f, "Could not read campaign file: {:?}", kind),
+            LoadError::Corrupt(msg) => write!(f, "Campaign file is corrupt: {}", msg),
+            LoadError::UnsupportedVersion(version) => write!(f, "Unsupported campaign file version {}", version),
+        }
+    }
+}
+
+#[cfg(test)]
+mod persistence_tests {
+    use super::*;
+    use super::super::campaign_tests::text_content;
+    use super::super::{ DanglingLinks, EntitySort };
+    fn sample_campaign() -> Campaign {
+        let mut camp = Campaign::new("C".to_string());
+        let e = camp.new_entity_of_kind("E".to_string(), None).unwrap();
+        camp.new_entity_of_kind("F".to_string(), None).unwrap();
+        camp.update_entity(e, None, text_content("Hello *world*\n[F](F)")).unwrap();
+        camp
+    }
+    fn temp_file(name : &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("gm_unleashed_{}_{}.{}", name, std::process::id(), FILE_EXTENSION))
+    }
+    #[test]
+    fn round_trip_in_memory() {
+        let original = sample_campaign();
+        let camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        assert_eq!(camp.name(), "C");
+        assert_eq!(camp.entities().len(), 2);
+        let e = camp.entity_by_name("E").unwrap();
+        let f = camp.entity_by_name("F").unwrap();
+        assert_eq!(e.id(), original.resolve("E").unwrap());
+        assert_eq!(f.id(), original.resolve("F").unwrap());
+        assert_eq!(e.content().text, "Hello *world*\n[F](F)");
+        assert_eq!(f.content().text, "");
+        assert_eq!(camp.links_to(f.id()), vec![e.id()]);
+    }
+    #[test]
+    fn ids_are_not_reused_after_reload() {
+        let mut original = sample_campaign();
+        let f = original.resolve("F").unwrap();
+        original.delete_entity(f, DanglingLinks::Keep).unwrap();
+        let mut camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        assert_ne!(camp.new_entity_of_kind("G".to_string(), None).unwrap(), f);
+    }
+    #[test]
+    fn edit_order_survives_reload() {
+        let mut original = sample_campaign();
+        let f = original.resolve("F").unwrap();
+        original.update_entity(f, None, text_content("latest")).unwrap();
+        let camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        assert_eq!(camp.sorted_entities(EntitySort::RecentlyEdited)[0].id(), f);
+    }
+    #[test]
+    fn kinds_survive_reload() {
+        let mut original = sample_campaign();
+        let deity = original.new_kind("Deity".to_string(), [1.0, 0.5, 0.0, 1.0], vec![FieldSchema::new("Domain", FieldType::Text)]).unwrap();
+        let e = original.resolve("E").unwrap();
+        let content = original.entity(e).unwrap().content().clone();
+        original.update_entity(e, Some(deity), content).unwrap();
+        let camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        let kind = camp.kind(deity).unwrap();
+        assert_eq!(kind.name(), "Deity");
+        assert_eq!(kind.color(), [1.0, 0.5, 0.0, 1.0]);
+        assert_eq!(kind.fields, vec![FieldSchema::new("Domain", FieldType::Text)]);
+        assert_eq!(camp.entity(e).unwrap().kind(), Some(deity));
+        assert!(camp.kind_by_name("NPC").unwrap().builtin);
+    }
+    #[test]
+    fn fields_survive_reload() {
+        let mut original = sample_campaign();
+        let f = original.resolve("F").unwrap();
+        let content = EntityContent {
+            text : String::new(),
+            fields : vec![
+                Field::new("Ruler", FieldValue::EntityReference("E".to_string())),
+                Field::new("Population", FieldValue::Number(1200.0)),
+                Field::new("Walled", FieldValue::Boolean(true)),
+                Field::new("Exports", FieldValue::List(vec!["Wool".to_string(), "Ale".to_string()])),
+            ],
+        };
+        original.update_entity(f, None, content).unwrap();
+        let camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        assert_eq!(camp.entity(f).unwrap().content().fields, original.entity(f).unwrap().content().fields);
+        assert_eq!(camp.links_to(original.resolve("E").unwrap()), vec![f]);
+    }
+    #[test]
+    fn revisions_survive_reload() {
+        let mut original = sample_campaign();
+        let e = original.resolve("E").unwrap();
+        original.update_entity(e, None, text_content("Goodbye")).unwrap();
+        let camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        assert_eq!(camp.revisions(e), original.revisions(e));
+        assert_eq!(camp.revisions(e).len(), 2);
+        assert_eq!(camp.revisions(e)[0].content().text, "Hello *world*\n[F](F)");
+    }
+    #[test]
+    fn retention_policy_survives_reload_and_prunes() {
+        let mut original = sample_campaign();
+        let e = original.resolve("E").unwrap();
+        original.update_entity(e, None, text_content("Goodbye")).unwrap();
+        original.retention_policy = RetentionPolicy{ keep_all_for : 0, then_one_per : 1, max_revisions : 1 };
+        let camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        assert_eq!(camp.retention_policy, original.retention_policy);
+        let texts : Vec<&str> = camp.revisions(e).iter().map(|revision| { revision.content().text.as_str() }).collect();
+        assert_eq!(texts, vec!["Goodbye"]);
+    }
+    #[test]
+    fn revisions_of_deleted_entities_are_not_saved() {
+        let mut original = sample_campaign();
+        let e = original.resolve("E").unwrap();
+        original.delete_entity(e, DanglingLinks::Keep).unwrap();
+        let mut camp = deserialize(&serialize(&original).unwrap()).unwrap();
+        assert!(camp.undo().is_none());
+        assert!(camp.revisions(e).is_empty());
+    }
+    #[test]
+    fn version_1_files_still_load() {
+        let data = "{ \"version\" : 1, \"name\" : \"C\", \"entities\" : [{ \"name\" : \"E\", \"text\" : \"[F](F)\" }, { \"name\" : \"F\", \"text\" : \"\" }] }";
+        let camp = deserialize(data).unwrap();
+        let e = camp.resolve("E").unwrap();
+        let f = camp.resolve("F").unwrap();
+        assert_ne!(e, f);
+        assert_eq!(camp.links_to(f), vec![e]);
+        assert_eq!(camp.entity(e).unwrap().kind(), None);
+        assert!(camp.kind_by_name("NPC").is_some());
+    }
+    #[test]
+    fn version_2_files_still_load() {
+        let data = "{ \"version\" : 2, \"name\" : \"C\", \"next_id\" : 8, \"entities\" : [{ \"id\" : 3, \"name\" : \"E\", \"text\" : \"[[F]]\", \"last_edited\" : 2 }, { \"id\" : 5, \"name\" : \"F\", \"text\" : \"\", \"last_edited\" : 1 }] }";
+        let camp = deserialize(data).unwrap();
+        assert_eq!(camp.resolve("E"), Some(EntityId(3)));
+        assert_eq!(camp.links_to(EntityId(5)), vec![EntityId(3)]);
+        assert_eq!(camp.entity(EntityId(3)).unwrap().kind(), None);
+        assert!(camp.kind_by_name("NPC").is_some());
+    }
+    #[test]
+    fn version_3_files_still_load() {
+        let data = "{ \"version\" : 3, \"name\" : \"C\", \"kinds\" : [{ \"id\" : 1, \"name\" : \"Deity\", \"color\" : [1.0, 1.0, 0.0, 1.0], \"builtin\" : false }], \"entities\" : [{ \"id\" : 1, \"name\" : \"E\", \"text\" : \"\", \"kind\" : 1 }] }";
+        let camp = deserialize(data).unwrap();
+        assert!(camp.kind(KindId(1)).unwrap().fields.is_empty());
+        assert_eq!(camp.entity(EntityId(1)).unwrap().kind(), Some(KindId(1)));
+        assert!(camp.entity(EntityId(1)).unwrap().content().fields.is_empty());
+    }
+    #[test]
+    fn version_4_files_still_load() {
+        let data = "{ \"version\" : 4, \"name\" : \"C\", \"entities\" : [{ \"id\" : 1, \"name\" : \"E\", \"text\" : \"Queen\", \"fields\" : [{ \"name\" : \"Age\", \"value\" : { \"Number\" : 40.0 } }] }] }";
+        let camp = deserialize(data).unwrap();
+        assert_eq!(camp.entity(EntityId(1)).unwrap().content().fields, vec![Field::new("Age", FieldValue::Number(40.0))]);
+        assert!(camp.revisions(EntityId(1)).is_empty());
+        assert_eq!(camp.retention_policy, RetentionPolicy::default());
+    }
+    #[test]
+    fn version_1_names_differing_in_case_are_disambiguated() {
+        let data = "{ \"version\" : 1, \"name\" : \"C\", \"entities\" : [{ \"name\" : \"E\", \"text\" : \"[big](E) [small](e)\" }, { \"name\" : \"e\", \"text\" : \"[big](E)\" }, { \"name\" : \"e 2\", \"text\" : \"\" }] }";
+        let camp = deserialize(data).unwrap();
+        let big = camp.resolve("E").unwrap();
+        let small = camp.resolve("e 3").unwrap();
+        assert_eq!(camp.entity(small).unwrap().content().text, "[big](E)");
+        assert_eq!(camp.entity(big).unwrap().content().text, "[big](E) [small](e 3)");
+        assert_eq!(camp.links_to(small), vec![big]);
+        assert!(camp.resolve("e 2").is_some());
+    }
+    #[test]
+    fn round_trip_on_disk() {
+        let path = temp_file("round_trip");
+        save(&sample_campaign(), &path).unwrap();
+        let camp = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(camp.entity_by_name("E").unwrap().name(), "E");
+        assert_eq!(camp.entity_by_name("E").unwrap().content().text, "Hello *world*\n[F](F)");
+    }
+    #[test]
+    fn saving_replaces_the_file_without_leftovers() {
+        let path = temp_file("replace");
+        save(&Campaign::new("Old".to_string()), &path).unwrap();
+        save(&sample_campaign(), &path).unwrap();
+        assert_eq!(load(&path).unwrap().name(), "C");
+        assert!(!temp_path(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+        let unwritable = temp_file("no_such_dir").join("campaign.gmu");
+        assert!(matches!(save(&sample_campaign(), &unwritable), Err(SaveError::Io(_))));
+        assert!(!temp_path(&unwritable).exists());
+    }
+    #[test]
+    fn missing_file() {
+        assert_eq!(load(temp_file("does_not_exist")).err(), Some(LoadError::NotFound));
+    }
+    #[test]
+    fn corrupt_data() {
+        match deserialize("{ \"version\" : 1, \"name\" : ") {
+            Err(LoadError::Corrupt(_)) => {}
+            _ => panic!("Expected corrupt data error"),
+        }
+    }
+    #[test]
+    fn duplicate_entities_are_corrupt() {
+        let data = "{ \"version\" : 1, \"name\" : \"C\", \"entities\" : [{ \"name\" : \"E\", \"text\" : \"\" }, { \"name\" : \"E\", \"text\" : \"\" }] }";
+        match deserialize(data) {
+            Err(LoadError::Corrupt(_)) => {}
+            _ => panic!("Expected corrupt data error"),
+        }
+    }
+    #[test]
+    fn unsupported_version() {
+        let data = format!("{{ \"version\" : {}, \"name\" : \"C\", \"entities\" : [] }}", FORMAT_VERSION + 1);
+        assert_eq!(deserialize(&data).err(), Some(LoadError::UnsupportedVersion(FORMAT_VERSION + 1)));
+    }
+}
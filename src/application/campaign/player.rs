@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use gm_unleashed_md::{ Document, tokenize, parse_document, remove_secrets };
+use super::{ Campaign, EntityId, FieldValue };
+use super::links::LinkIndex;
+use super::fields::reference_targets;
+
+/// Parses `text` with every secret removed. Anything shown to players must be built from this.
+pub fn player_document(text : &str) -> Document {
+    let mut document = parse_document(tokenize(text));
+    document.strip_secrets();
+    document
+}
+
+/// Field values are shown as written, so secrets are cut out of their text instead of parsed away.
+pub fn player_field_value(value : &FieldValue) -> FieldValue {
+    match value {
+        FieldValue::Text(text) => FieldValue::Text(remove_secrets(text)),
+        FieldValue::List(items) => FieldValue::List(items.iter().map(|item| { remove_secrets(item) }).filter(|item| { !item.trim().is_empty() }).collect()),
+        value => value.clone(),
+    }
+}
+
+/// The campaign as the players see it: entity texts without secrets, and only the links that are left afterwards.
+pub struct PlayerView<'a> {
+    campaign : &'a Campaign,
+    documents : HashMap<EntityId, Document>,
+    links : LinkIndex,
+}
+
+impl<'a> PlayerView<'a> {
+    pub fn new(campaign : &'a Campaign) -> Self {
+        let mut documents = HashMap::new();
+        let mut links = LinkIndex::new();
+        for entity in campaign.entities().values() {
+            let document = player_document(&entity.content().text);
+            let mut targets : Vec<String> = document.link_targets().into_iter().map(str::to_string).collect();
+            targets.extend(reference_targets(&entity.content().fields));
+            links.update(entity.id(), targets);
+            documents.insert(entity.id(), document);
+        }
+        PlayerView {
+            campaign,
+            documents,
+            links,
+        }
+    }
+
+    pub fn campaign(&self) -> &'a Campaign { self.campaign }
+
+    pub fn document(&self, id : EntityId) -> Option<&Document> { self.documents.get(&id) }
+
+    /// The entities whose player-visible content links to `id`.
+    pub fn links_to(&self, id : EntityId) -> Vec<EntityId> {
+        match self.campaign.entity(id) {
+            Some(entity) => self.links.links_to(entity.name()),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod player_view_tests {
+    use super::*;
+    use super::super::campaign_tests::text_content;
+    use gm_unleashed_md::normalize;
+    #[test]
+    fn secrets_and_their_links_are_hidden() {
+        let mut camp = Campaign::new("C".to_string());
+        let spy = camp.new_entity_of_kind("Spy".to_string(), None).unwrap();
+        let duke = camp.new_entity_of_kind("Duke".to_string(), None).unwrap();
+        let inn = camp.new_entity_of_kind("Inn".to_string(), None).unwrap();
+        camp.update_entity(spy, None, text_content("Drinks at the [[Inn]]{{, paid by the [[Duke]]}}\n\n!> Reports to [[Duke]]")).unwrap();
+        let view = PlayerView::new(&camp);
+        assert_eq!(view.document(spy).unwrap().link_targets(), vec!["Inn"]);
+        assert_eq!(view.links_to(inn), vec![spy]);
+        assert!(view.links_to(duke).is_empty());
+        assert_eq!(camp.links_to(duke), vec![spy]);
+    }
+    #[test]
+    fn unmatched_secret_markers_hide_text() {
+        assert_eq!(normalize(&player_document("Public {{secret")), "Public ");
+        assert_eq!(normalize(&player_document("{{the duke\n\nis the thief}}")), "");
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use super::{ EntityId, EntityContent, FieldValue };
+use super::diff::{ DiffSpan, word_diff };
+
+const SECONDS_PER_DAY : u64 = 24 * 60 * 60;
+
+/// Keeps every revision younger than `keep_all_for` seconds, then the latest one of each `then_one_per` seconds.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct RetentionPolicy {
+    pub keep_all_for : u64,
+    pub then_one_per : u64,
+    pub max_revisions : usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_all_for : SECONDS_PER_DAY,
+            then_one_per : SECONDS_PER_DAY,
+            max_revisions : 50,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Revision {
+    timestamp : u64,
+    content : EntityContent,
+}
+
+impl Revision {
+    pub fn new(timestamp : u64, content : EntityContent) -> Self {
+        Revision {
+            timestamp,
+            content,
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 { self.timestamp }
+    pub fn content(&self) -> &EntityContent { &self.content }
+    pub fn label(&self) -> String { format_timestamp(self.timestamp) }
+}
+
+pub struct RevisionLog {
+    revisions : HashMap<EntityId, Vec<Revision>>,
+}
+
+impl RevisionLog {
+    pub fn new() -> Self {
+        RevisionLog {
+            revisions : HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, id : EntityId, revision : Revision, policy : &RetentionPolicy) {
+        let revisions = self.revisions.entry(id).or_default();
+        if revisions.last().is_some_and(|latest| { latest.content == revision.content }) {
+            return;
+        }
+        let now = revision.timestamp;
+        revisions.push(revision);
+        prune(revisions, policy, now);
+    }
+
+    pub fn restore(&mut self, id : EntityId, mut revisions : Vec<Revision>, policy : &RetentionPolicy) {
+        revisions.sort_by_key(|revision| { revision.timestamp });
+        let now = revisions.last().map_or(0, |latest| { latest.timestamp });
+        prune(&mut revisions, policy, now);
+        self.revisions.insert(id, revisions);
+    }
+
+    pub fn revisions(&self, id : EntityId) -> &[Revision] {
+        self.revisions.get(&id).map_or(&[], |revisions| { revisions.as_slice() })
+    }
+}
+
+fn prune(revisions : &mut Vec<Revision>, policy : &RetentionPolicy, now : u64) {
+    let cutoff = now.saturating_sub(policy.keep_all_for);
+    let period = |revision : &Revision| { revision.timestamp / policy.then_one_per.max(1) };
+    let mut kept : Vec<Revision> = Vec::new();
+    for revision in revisions.drain(..).rev() {
+        let superseded = revision.timestamp < cutoff && kept.last().is_some_and(|newer| {
+            newer.timestamp < cutoff && period(newer) == period(&revision)
+        });
+        if !superseded {
+            kept.push(revision);
+        }
+    }
+    kept.truncate(policy.max_revisions.max(1));
+    kept.reverse();
+    *revisions = kept;
+}
+
+pub fn current_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| { duration.as_secs() })
+}
+
+pub fn format_timestamp(timestamp : u64) -> String {
+    // Days to civil dates, after Howard Hinnant's algorithm, in 400 year eras starting on the 1st of March.
+    let days = (timestamp / SECONDS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let seconds_of_day = timestamp % SECONDS_PER_DAY;
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, seconds_of_day / 3600, seconds_of_day % 3600 / 60)
+}
+
+pub fn revision_diff(old : &Revision, new : &Revision) -> Vec<DiffSpan> {
+    word_diff(&comparable_text(&old.content), &comparable_text(&new.content))
+}
+
+fn comparable_text(content : &EntityContent) -> String {
+    let mut text = content.text.clone();
+    for field in &content.fields {
+        let value = match &field.value {
+            FieldValue::Text(value) | FieldValue::EntityReference(value) => value.clone(),
+            FieldValue::Number(number) => number.to_string(),
+            FieldValue::Boolean(true) => "yes".to_string(),
+            FieldValue::Boolean(false) => "no".to_string(),
+            FieldValue::List(items) => items.join(", "),
+        };
+        text.push_str(&format!("\n{}: {}", field.name, value));
+    }
+    text
+}
+
+#[cfg(test)]
+mod revision_tests {
+    use super::*;
+    use super::super::campaign_tests::text_content;
+    use super::super::{ Field, DiffKind };
+    const A : EntityId = EntityId(1);
+    const HOUR : u64 = 60 * 60;
+    fn revision(timestamp : u64, text : &str) -> Revision {
+        Revision::new(timestamp, text_content(text))
+    }
+    fn timestamps(log : &RevisionLog) -> Vec<u64> {
+        log.revisions(A).iter().map(Revision::timestamp).collect()
+    }
+    #[test]
+    fn unchanged_content_is_not_recorded() {
+        let mut log = RevisionLog::new();
+        let policy = RetentionPolicy::default();
+        log.record(A, revision(1, "a"), &policy);
+        log.record(A, revision(2, "a"), &policy);
+        log.record(A, revision(3, "b"), &policy);
+        assert_eq!(timestamps(&log), vec![1, 3]);
+        assert!(log.revisions(EntityId(2)).is_empty());
+    }
+    #[test]
+    fn old_revisions_are_thinned_out() {
+        let mut log = RevisionLog::new();
+        let policy = RetentionPolicy{ keep_all_for : 10 * HOUR, then_one_per : 24 * HOUR, max_revisions : 5 };
+        for (idx, timestamp) in [HOUR, 2 * HOUR, 30 * HOUR, 40 * HOUR, 41 * HOUR].iter().enumerate() {
+            log.record(A, revision(*timestamp, &idx.to_string()), &policy);
+        }
+        assert_eq!(timestamps(&log), vec![2 * HOUR, 30 * HOUR, 40 * HOUR, 41 * HOUR]);
+        log.record(A, revision(60 * HOUR, "later"), &policy);
+        assert_eq!(timestamps(&log), vec![2 * HOUR, 41 * HOUR, 60 * HOUR]);
+        for idx in 1..=10 {
+            log.record(A, revision(60 * HOUR + idx, &idx.to_string()), &policy);
+        }
+        assert_eq!(timestamps(&log), (6..=10).map(|idx| { 60 * HOUR + idx }).collect::<Vec<u64>>());
+    }
+    #[test]
+    fn restored_revisions_are_pruned() {
+        let mut log = RevisionLog::new();
+        let policy = RetentionPolicy{ keep_all_for : HOUR, then_one_per : 24 * HOUR, max_revisions : 2 };
+        log.restore(A, vec![revision(3 * HOUR, "c"), revision(HOUR, "a"), revision(2 * HOUR, "b")], &policy);
+        assert_eq!(timestamps(&log), vec![2 * HOUR, 3 * HOUR]);
+    }
+    #[test]
+    fn timestamps_are_formatted_as_utc_dates() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+    }
+    #[test]
+    fn diffs_include_fields() {
+        let old = revision(1, "The fort");
+        let mut new = revision(2, "The fort");
+        new.content.fields.push(Field::new("Walled", FieldValue::Boolean(true)));
+        let diff = revision_diff(&old, &new);
+        assert_eq!(diff.last().unwrap().kind, DiffKind::Added);
+        assert_eq!(diff.last().unwrap().text, "\nWalled: yes");
+    }
+}
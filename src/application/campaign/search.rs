@@ -0,0 +1,248 @@
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::cmp::Reverse;
+use gm_unleashed_md::{ SourceRange, tokenize, parse_document };
+use super::{ EntityId, EntityContent, FieldValue };
+
+const NAME_WEIGHT : u32 = 5;
+const EXACT_WEIGHT : u32 = 2;
+const SNIPPET_CONTEXT : usize = 40;
+const ELLIPSIS : &str = "…";
+
+/// A piece of text with the ranges to highlight in it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Snippet {
+    pub text : String,
+    pub highlights : Vec<SourceRange>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SearchHit {
+    pub entity : EntityId,
+    pub score : u32,
+    pub name : Snippet,
+    pub snippet : Snippet,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Posting {
+    name_hits : u32,
+    text_hits : u32,
+}
+
+struct IndexedEntity {
+    name : String,
+    text : String,
+    words : HashSet<String>,
+}
+
+/// An inverted index from every word of an entity's name and plain text to the entities containing it. Words are
+/// sorted, so all words starting with a prefix are found together.
+pub struct SearchIndex {
+    postings : BTreeMap<String, HashMap<EntityId, Posting>>,
+    entities : HashMap<EntityId, IndexedEntity>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        SearchIndex {
+            postings : BTreeMap::new(),
+            entities : HashMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, id : EntityId, name : &str, content : &EntityContent) {
+        self.remove(id);
+        let text = searchable_text(content);
+        let mut words = HashSet::new();
+        for range in word_ranges(name) {
+            self.posting(&mut words, &name[range], id).name_hits += 1;
+        }
+        for range in word_ranges(&text) {
+            self.posting(&mut words, &text[range], id).text_hits += 1;
+        }
+        self.entities.insert(id, IndexedEntity{ name : name.to_string(), text, words });
+    }
+
+    fn posting(&mut self, words : &mut HashSet<String>, word : &str, id : EntityId) -> &mut Posting {
+        let word = word_key(word);
+        words.insert(word.clone());
+        self.postings.entry(word).or_default().entry(id).or_default()
+    }
+
+    pub fn remove(&mut self, id : EntityId) {
+        if let Some(entity) = self.entities.remove(&id) {
+            for word in entity.words {
+                if let Some(postings) = self.postings.get_mut(&word) {
+                    postings.remove(&id);
+                    if postings.is_empty() {
+                        self.postings.remove(&word);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds the entities containing every word of `query`, each as a whole word or the start of one, best matches
+    /// first. Matches in the name count more than matches in the text, and whole words more than prefixes.
+    pub fn search(&self, query : &str) -> Vec<SearchHit> {
+        let terms : Vec<String> = word_ranges(query).map(|range| { word_key(&query[range]) }).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let mut scores : Option<HashMap<EntityId, u32>> = None;
+        let mut matched_words = HashSet::new();
+        for term in &terms {
+            let mut term_scores : HashMap<EntityId, u32> = HashMap::new();
+            for (word, postings) in self.postings.range(term.clone()..).take_while(|(word, _)| { word.starts_with(term.as_str()) }) {
+                let weight = if word == term { EXACT_WEIGHT } else { 1 };
+                for (id, posting) in postings {
+                    *term_scores.entry(*id).or_default() += weight * (NAME_WEIGHT * posting.name_hits + posting.text_hits);
+                }
+                matched_words.insert(word.as_str());
+            }
+            scores = Some(match scores {
+                None => term_scores,
+                Some(scores) => scores.into_iter().filter_map(|(id, score)| {
+                    term_scores.get(&id).map(|term_score| { (id, score + term_score) })
+                }).collect(),
+            });
+        }
+        let mut hits : Vec<SearchHit> = scores.unwrap_or_default().into_iter().map(|(id, score)| {
+            let entity = &self.entities[&id];
+            SearchHit {
+                entity : id,
+                score,
+                name : Snippet{ text : entity.name.clone(), highlights : highlights(&entity.name, &matched_words) },
+                snippet : snippet(&entity.text, &matched_words),
+            }
+        }).collect();
+        hits.sort_by_key(|hit| { (Reverse(hit.score), hit.name.text.to_lowercase(), hit.entity) });
+        hits
+    }
+}
+
+/// The plain text of the notes followed by one `Name: value` line per field that holds text.
+fn searchable_text(content : &EntityContent) -> String {
+    let mut text = parse_document(tokenize(content.text.as_str())).plain_text();
+    for field in &content.fields {
+        let value = match &field.value {
+            FieldValue::Text(value) | FieldValue::EntityReference(value) => value.clone(),
+            FieldValue::List(items) => items.join(", "),
+            FieldValue::Number(_) | FieldValue::Boolean(_) => continue,
+        };
+        text.push_str(&format!("\n{}: {}", field.name, value));
+    }
+    text
+}
+
+fn word_ranges(text : &str) -> impl Iterator<Item=SourceRange> + '_ {
+    text.char_indices().filter(move |&(idx, ch)| {
+        ch.is_alphanumeric() && !text[..idx].chars().next_back().is_some_and(char::is_alphanumeric)
+    }).map(move |(start, _)| {
+        let end = text[start..].find(|ch : char| { !ch.is_alphanumeric() }).map_or(text.len(), |len| { start + len });
+        start..end
+    })
+}
+
+fn word_key(word : &str) -> String {
+    word.to_lowercase()
+}
+
+fn highlights(text : &str, matched_words : &HashSet<&str>) -> Vec<SourceRange> {
+    word_ranges(text).filter(|range| { matched_words.contains(word_key(&text[range.clone()]).as_str()) }).collect()
+}
+
+/// The text around the first match, cut at word boundaries, or the start of the text when only the name matched.
+fn snippet(text : &str, matched_words : &HashSet<&str>) -> Snippet {
+    let all = highlights(text, matched_words);
+    let first = all.first().map_or(0, |range| { range.start });
+    let start = word_ranges(&text[..first]).map(|range| { range.start })
+        .find(|&start| { first - start <= SNIPPET_CONTEXT })
+        .unwrap_or(first);
+    let first_end = all.first().map_or(0, |range| { range.end });
+    let mut end = word_ranges(text).map(|range| { range.end }).take_while(|&end| { end <= first + SNIPPET_CONTEXT * 2 }).last()
+        .unwrap_or(first_end)
+        .max(first_end);
+    if word_ranges(&text[end..]).next().is_none() {
+        end = text.trim_end().len();
+    }
+    let mut snippet = String::new();
+    let mut offset = 0;
+    if start > 0 {
+        snippet.push_str(ELLIPSIS);
+        offset = ELLIPSIS.len();
+    }
+    snippet.push_str(&text[start..end]);
+    if end < text.trim_end().len() {
+        snippet.push_str(ELLIPSIS);
+    }
+    let highlights = all.into_iter().filter(|range| { range.start >= start && range.end <= end }).map(|range| {
+        range.start - start + offset..range.end - start + offset
+    }).collect();
+    Snippet{ text : snippet.replace('\n', " "), highlights }
+}
+
+#[cfg(test)]
+mod search_tests {
+    use super::*;
+    use super::super::campaign_tests::text_content;
+    const A : EntityId = EntityId(1);
+    const B : EntityId = EntityId(2);
+    const C : EntityId = EntityId(3);
+    fn sample_index() -> SearchIndex {
+        let mut index = SearchIndex::new();
+        index.update(A, "Fort North", &text_content("A *ruined* fort held by [[Elara]]."));
+        index.update(B, "Elara", &text_content("Queen of the north, known to fortify everything."));
+        index.update(C, "Goblin", &text_content("Lives in a cave"));
+        index
+    }
+    fn found(index : &SearchIndex, query : &str) -> Vec<EntityId> {
+        index.search(query).into_iter().map(|hit| { hit.entity }).collect()
+    }
+    #[test]
+    fn names_rank_above_text() {
+        let index = sample_index();
+        assert_eq!(found(&index, "elara"), vec![B, A]);
+        assert_eq!(found(&index, "north"), vec![A, B]);
+    }
+    #[test]
+    fn prefixes_match_and_every_word_is_required() {
+        let index = sample_index();
+        assert_eq!(found(&index, "fort"), vec![A, B]);
+        assert_eq!(found(&index, "for nor"), vec![A, B]);
+        assert_eq!(found(&index, "ruin fort"), vec![A]);
+        assert!(found(&index, "ruin cave").is_empty());
+        assert!(found(&index, "  ").is_empty());
+    }
+    #[test]
+    fn markup_is_not_indexed() {
+        let index = sample_index();
+        assert!(found(&index, "[[").is_empty());
+        assert_eq!(found(&index, "ruined"), vec![A]);
+    }
+    #[test]
+    fn updates_replace_and_remove_words() {
+        let mut index = sample_index();
+        index.update(C, "Goblin", &text_content("Burned the fort"));
+        assert_eq!(found(&index, "cave").len(), 0);
+        assert_eq!(found(&index, "burn"), vec![C]);
+        index.remove(C);
+        assert!(found(&index, "goblin").is_empty());
+        assert!(index.postings.keys().all(|word| { !word.starts_with("burn") }));
+    }
+    #[test]
+    fn snippets_highlight_matches() {
+        let index = sample_index();
+        let hit = &index.search("ruin")[0];
+        assert_eq!(hit.snippet.text, "A ruined fort held by Elara.");
+        assert_eq!(hit.snippet.highlights, vec![2..8]);
+        let mut long = SearchIndex::new();
+        let text = format!("{} dragon {}", "word ".repeat(20), "tail ".repeat(20));
+        long.update(A, "Lair", &text_content(&text));
+        let hit = &long.search("dragon")[0];
+        assert!(hit.snippet.text.starts_with(ELLIPSIS) && hit.snippet.text.ends_with(ELLIPSIS));
+        let highlighted : Vec<&str> = hit.snippet.highlights.iter().map(|range| { &hit.snippet.text[range.clone()] }).collect();
+        assert_eq!(highlighted, vec!["dragon"]);
+        assert_eq!(index.search("elara")[0].name.highlights, vec![0..5]);
+    }
+}
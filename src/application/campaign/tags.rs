@@ -0,0 +1,177 @@
+use std::collections::{ HashMap, BTreeSet };
+use gm_unleashed_md::{ Tag, tokenize, extract_tags };
+use super::EntityId;
+
+const OR_KEYWORD : &str = "or";
+const AND_KEYWORD : &str = "and";
+const OR_SEPARATOR : char = '|';
+const TAG_PREFIX : char = '#';
+
+pub struct TagIndex {
+    tags_of : HashMap<EntityId, BTreeSet<String>>,
+    tagged : HashMap<String, BTreeSet<EntityId>>,
+}
+
+impl TagIndex {
+    pub fn new() -> Self {
+        TagIndex {
+            tags_of : HashMap::new(),
+            tagged : HashMap::new(),
+        }
+    }
+
+    pub fn update<T>(&mut self, source : EntityId, tags : T)
+        where T : IntoIterator<Item=String>
+    {
+        self.remove(source);
+        let tags : BTreeSet<String> = tags.into_iter().map(|tag| { tag_key(&tag) }).collect();
+        for tag in &tags {
+            self.tagged.entry(tag.clone()).or_default().insert(source);
+        }
+        if !tags.is_empty() {
+            self.tags_of.insert(source, tags);
+        }
+    }
+
+    pub fn remove(&mut self, source : EntityId) {
+        if let Some(old_tags) = self.tags_of.remove(&source) {
+            for tag in old_tags {
+                if let Some(sources) = self.tagged.get_mut(&tag) {
+                    sources.remove(&source);
+                    if sources.is_empty() {
+                        self.tagged.remove(&tag);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Every tag in use, sorted.
+    pub fn all_tags(&self) -> Vec<&str> {
+        let mut tags : Vec<&str> = self.tagged.keys().map(|tag| { tag.as_str() }).collect();
+        tags.sort();
+        tags
+    }
+
+    /// The entities matching `query`, in id order.
+    pub fn query(&self, query : &TagQuery) -> Vec<EntityId> {
+        let mut matches = BTreeSet::new();
+        for all_of in &query.alternatives {
+            let mut sets = all_of.iter().map(|tag| { self.tagged.get(tag) });
+            let first = match sets.next() {
+                Some(Some(first)) => first.clone(),
+                _ => continue,
+            };
+            let common = sets.try_fold(first, |common, set| {
+                set.map(|set| { common.intersection(set).cloned().collect::<BTreeSet<EntityId>>() })
+            });
+            matches.extend(common.unwrap_or_default());
+        }
+        matches.into_iter().collect()
+    }
+}
+
+pub fn text_tags(text : &str) -> Vec<String> {
+    extract_tags(&tokenize(text)).iter().map(|tag| { tag.name().to_string() }).collect()
+}
+
+/// Tags compare without regard to case.
+pub fn tag_key(tag : &str) -> String {
+    tag.to_lowercase()
+}
+
+/// Tags that must all be present, in one or more alternatives of which any may match. Written as e.g.
+/// `#villain #act2 | #rival`, where `or` may stand in for `|` and `and` between tags is allowed but not needed.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TagQuery {
+    alternatives : Vec<Vec<String>>,
+}
+
+impl TagQuery {
+    pub fn parse(query : &str) -> Result<Self, TagQueryError> {
+        let mut alternatives = vec![Vec::new()];
+        for word in query.split_whitespace().flat_map(split_or_separators) {
+            if word == OR_SEPARATOR.to_string() || word.eq_ignore_ascii_case(OR_KEYWORD) {
+                alternatives.push(Vec::new());
+            } else if !word.eq_ignore_ascii_case(AND_KEYWORD) {
+                let name = word.strip_prefix(TAG_PREFIX).unwrap_or(word);
+                if Tag::new(name).is_none() {
+                    return Err(TagQueryError::InvalidTag(word.to_string()));
+                }
+                alternatives.last_mut().unwrap().push(tag_key(name));
+            }
+        }
+        if alternatives.len() == 1 && alternatives[0].is_empty() {
+            return Ok(TagQuery{ alternatives : Vec::new() });
+        }
+        if alternatives.iter().any(Vec::is_empty) {
+            return Err(TagQueryError::EmptyAlternative);
+        }
+        Ok(TagQuery{ alternatives })
+    }
+
+    /// An empty query filters nothing out.
+    pub fn is_empty(&self) -> bool { self.alternatives.is_empty() }
+}
+
+/// Splits `#a|#b` into `#a`, `|` and `#b`.
+fn split_or_separators(word : &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = word;
+    while let Some(idx) = rest.find(OR_SEPARATOR) {
+        parts.push(&rest[..idx]);
+        parts.push(&rest[idx..idx + OR_SEPARATOR.len_utf8()]);
+        rest = &rest[idx + OR_SEPARATOR.len_utf8()..];
+    }
+    parts.push(rest);
+    parts.into_iter().filter(|part| { !part.is_empty() }).collect()
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum TagQueryError {
+    InvalidTag(String),
+    EmptyAlternative,
+}
+
+#[cfg(test)]
+mod tag_index_tests {
+    use super::*;
+    const A : EntityId = EntityId(1);
+    const B : EntityId = EntityId(2);
+    const C : EntityId = EntityId(3);
+    fn sample_index() -> TagIndex {
+        let mut index = TagIndex::new();
+        index.update(A, text_tags("#villain of #Act2"));
+        index.update(B, text_tags("#villain"));
+        index.update(C, text_tags("#rival in #act2"));
+        index
+    }
+    fn query(index : &TagIndex, query : &str) -> Vec<EntityId> {
+        index.query(&TagQuery::parse(query).unwrap())
+    }
+    #[test]
+    fn tags_are_indexed_both_ways() {
+        let mut index = sample_index();
+        assert_eq!(query(&index, "#ACT2"), vec![A, C]);
+        assert_eq!(index.all_tags(), vec!["act2", "rival", "villain"]);
+        index.update(A, Vec::new());
+        index.remove(C);
+        assert_eq!(index.all_tags(), vec!["villain"]);
+    }
+    #[test]
+    fn queries_combine_and_with_or() {
+        let index = sample_index();
+        assert_eq!(query(&index, "#villain #act2"), vec![A]);
+        assert_eq!(query(&index, "#villain and act2"), vec![A]);
+        assert_eq!(query(&index, "#villain #act2 | #rival"), vec![A, C]);
+        assert_eq!(query(&index, "#rival OR #villain|#nobody"), vec![A, B, C]);
+        assert!(query(&index, "#villain #nobody").is_empty());
+    }
+    #[test]
+    fn malformed_queries_are_rejected() {
+        assert!(TagQuery::parse("  ").unwrap().is_empty());
+        assert_eq!(TagQuery::parse("#villain #1"), Err(TagQueryError::InvalidTag("#1".to_string())));
+        assert_eq!(TagQuery::parse("#villain or"), Err(TagQueryError::EmptyAlternative));
+        assert_eq!(TagQuery::parse("| #villain"), Err(TagQueryError::EmptyAlternative));
+    }
+}
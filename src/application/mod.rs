@@ -1,22 +1,42 @@
 use imgui::*;
+use std::path::PathBuf;
 use super::{ Fonts, FontStyle };
 
 mod ui_tools;
 
-use ui_tools::{ Button, TextField, markdown };
+use ui_tools::{ Button, TextField, FieldEditor, markdown, diagnostics, button, link_list, choice_list, colored_list_box, highlighted_text, diff_text };
 
 mod campaign;
 
-use campaign::{ Campaign, EntityContent };
+use campaign::{ 
+    Campaign, EntityId, EntityContent, EntitySort, KindId, FieldSchema, FieldType, Field, FieldValue,
+    NewEntityError, UpdateEntityError, RenameEntityError, DanglingLinks, persistence, export, name_key, retarget_links, clear_links, link_mentions,
+    player_document, TagQuery, TagQueryError, revision_diff 
+};
+use gm_unleashed_md::{ tokenize, parse_document };
+
+mod recent_campaigns;
+
+use recent_campaigns::RecentCampaigns;
 
 trait ApplicationState {
     fn build_gui(self : Box<Self>, ui : &Ui) -> Box<dyn ApplicationState>;
 }
 
 trait ApplicationSubstate {
-    fn build_gui(self : Box<Self>, ui : &Ui, fonts : &Fonts) -> Box<dyn ApplicationSubstate>;    
+    fn build_gui(self : Box<Self>, ui : &Ui, fonts : &Fonts, campaign : &Campaign) -> Box<dyn ApplicationSubstate>;    
     fn persist(&mut self, campaign : &mut Campaign);
     fn expired(&self) -> bool;
+    fn edited_entity(&self) -> Option<EntityId> { None }
+    fn requested_entity(&mut self) -> Option<EntityId> { None }
+    fn campaign_event(&mut self) -> Option<CampaignEvent> { None }
+    fn handle_event(&mut self, _event : &CampaignEvent) {}
+    fn status_message(&mut self) -> Option<String> { None }
+}
+
+enum CampaignEvent {
+    Renamed { id : EntityId, old_name : String, new_name : String },
+    Deleted { name : String, dangling_links : DanglingLinks },
 }
 
 struct EmptyState;
@@ -27,6 +47,7 @@ impl ApplicationState for EmptyState {
 struct InitialState {
     title : ImString,
     create_button : Button,
+    open_button : Button,
     fonts : Fonts,
 }
 
@@ -34,12 +55,18 @@ impl ApplicationState for InitialState {
     fn build_gui(mut self : Box<Self>, ui : &Ui) -> Box<dyn ApplicationState> {
         let title = &self.title;
         let create_button = &mut self.create_button;
-        Window::new(&title).build(
+        let open_button = &mut self.open_button;
+        Window::new(title).build(
             ui,
-            || { create_button.build_gui(ui); }
+            || { 
+                create_button.build_gui(ui); 
+                open_button.build_gui(ui);
+            }
         );
         if create_button.pressed() {
             Box::new(CreateCampaignState::new(self.fonts))
+        } else if open_button.pressed() {
+            Box::new(OpenCampaignState::new(self.fonts))
         } else {
             self
         }
@@ -48,13 +75,100 @@ impl ApplicationState for InitialState {
 
 impl InitialState {
     const LABEL_CREATE : &'static str = "Create new campaign"; 
+    const LABEL_OPEN : &'static str = "Open campaign"; 
     pub fn new(fonts : Fonts) -> Self { InitialState{
         title : ImString::new(Application::MAIN_MENU_TITLE),
         create_button : Button::new(ImString::new(InitialState::LABEL_CREATE)),
+        open_button : Button::new(ImString::new(InitialState::LABEL_OPEN)),
         fonts,
     } }
 }
 
+struct OpenCampaignState {
+    title : ImString,
+    recent_label : ImString,
+    recent : RecentCampaigns,
+    recent_labels : Vec<ImString>,
+    path_field : TextField,
+    open_button : Button,
+    error_text : ImString,
+    fonts : Fonts,
+}
+
+impl ApplicationState for OpenCampaignState {
+    fn build_gui(mut self : Box<Self>, ui : &Ui) -> Box<dyn ApplicationState> {
+        let title = &self.title;
+        let recent_label = &self.recent_label;
+        let recent_labels = &self.recent_labels;
+        let path_field = &mut self.path_field;
+        let open_button = &mut self.open_button;
+        let error_text = &self.error_text;
+        let mut chosen_recent = None;
+        Window::new(title).size([400.0, 300.0], Condition::FirstUseEver).build(
+            ui,
+            || {
+                ui.text(recent_label);
+                for (idx, label) in recent_labels.iter().enumerate() {
+                    if button(ui, label) {
+                        chosen_recent = Some(idx);
+                    }
+                }
+                path_field.build_gui(ui);
+                open_button.build_gui(ui);
+                if !error_text.is_empty() {
+                    ui.text(error_text);
+                }
+            }
+        );
+        let path = if let Some(idx) = chosen_recent {
+            Some(self.recent.paths()[idx].clone())
+        } else if open_button.pressed() && !path_field.content().is_empty() {
+            Some(PathBuf::from(path_field.content().to_str()))
+        } else {
+            None
+        };
+        if let Some(path) = path {
+            match persistence::load(&path) {
+                Ok(campaign) => {
+                    self.recent.add(&path);
+                    let _ = self.recent.save();
+                    return Box::new(EditCampaignState::from_campaign(campaign, path, self.fonts));
+                }
+                Err(err) => {
+                    if err == persistence::LoadError::NotFound {
+                        self.recent.remove(&path);
+                        let _ = self.recent.save();
+                        self.recent_labels = OpenCampaignState::recent_labels(&self.recent);
+                    }
+                    self.error_text = ImString::new(format!("{}: {}", path.display(), err));
+                }
+            }
+        }
+        self
+    }
+}
+
+impl OpenCampaignState {
+    const LABEL_RECENT : &'static str = "Recent campaigns";
+    pub fn new(fonts : Fonts) -> Self {
+        let recent = RecentCampaigns::load_default();
+        OpenCampaignState {
+            title : ImString::new(Application::OPEN_CAMPAIGN_TITLE),
+            recent_label : ImString::new(OpenCampaignState::LABEL_RECENT),
+            recent_labels : OpenCampaignState::recent_labels(&recent),
+            recent,
+            path_field : TextField::new(ImString::new(Application::PATH_LABEL)),
+            open_button : Button::new(ImString::new(Application::OPEN_LABEL)),
+            error_text : ImString::new(""),
+            fonts,
+        }
+    }
+
+    fn recent_labels(recent : &RecentCampaigns) -> Vec<ImString> {
+        recent.paths().iter().map(|path| { ImString::new(path.display().to_string()) }).collect()
+    }
+}
+
 struct CreateCampaignState {
     title : ImString,
     name_field : TextField,
@@ -107,9 +221,21 @@ struct EditCampaignState {
     title : ImString,
     name_label : ImString,
     entities_label : ImString,
-    current_entity : i32,
+    selected_entity : Option<EntityId>,
+    sort : EntitySort,
+    kind_filter : Option<KindId>,
+    tag_filter_field : TextField,
+    search_field : TextField,
     create_entity_button : Button,
+    create_kind_button : Button,
     edit_entity_button : Button,
+    rename_entity_button : Button,
+    delete_entity_button : Button,
+    save_path_field : TextField,
+    save_button : Button,
+    export_path_field : TextField,
+    export_button : Button,
+    status_text : ImString,
     substates : Vec<Box<dyn ApplicationSubstate>>,
     campaign : Campaign,
     fonts : Fonts,
@@ -117,82 +243,268 @@ struct EditCampaignState {
 
 impl ApplicationState for EditCampaignState {
     fn build_gui(mut self : Box<Self>, ui : &Ui) -> Box<dyn ApplicationState> {
+        // Undo first, while nothing else borrows the state, so the list below already shows the result.
+        let mut events = self.undo_shortcut(ui);
         let title = &self.title;
         let name_label = &self.name_label;
         let entities_label = &self.entities_label;
-        let entity_names : Vec<ImString> = self.campaign.entities().keys().map(|name| { ImString::new(name) }).collect();
-        let entity_names : Vec<&ImStr> = entity_names.iter().map(|name| { name.as_ref() }).collect();
-        let current_entity = &mut self.current_entity;
+        let kind_filter = self.kind_filter;
+        let campaign = &self.campaign;
+        let tag_query = TagQuery::parse(self.tag_filter_field.content().to_str());
+        let tagged = match &tag_query {
+            Ok(query) if !query.is_empty() => Some(campaign.query_tags(query)),
+            _ => None,
+        };
+        let tag_error = tag_query.err().map(|err| {
+            match err {
+                TagQueryError::InvalidTag(tag) => ImString::new(format!("{} {}", Application::INVALID_TAG_MESSAGE, tag)),
+                TagQueryError::EmptyAlternative => ImString::new(Application::EMPTY_TAG_ALTERNATIVE_MESSAGE),
+            }
+        });
+        let all_tags = campaign.all_tags();
+        let mut clicked_tag = None;
+        let search_query = self.search_field.content().to_str().trim();
+        let hits = if search_query.is_empty() { None } else { Some(campaign.search(search_query)) };
+        let listed_entities = match &hits {
+            Some(hits) => hits.iter().filter_map(|hit| { campaign.entity(hit.entity) }).collect(),
+            None => campaign.sorted_entities(self.sort),
+        };
+        let sorted_entities : Vec<_> = listed_entities.into_iter().filter(|entity| {
+            (kind_filter.is_none() || entity.kind() == kind_filter) && tagged.as_ref().is_none_or(|tagged| { tagged.contains(&entity.id()) })
+        }).collect();
+        let entity_ids : Vec<EntityId> = sorted_entities.iter().map(|entity| { entity.id() }).collect();
+        let entity_items : Vec<(ImString, Option<[f32; 4]>)> = sorted_entities.iter().map(|entity| { 
+            match entity.kind().and_then(|kind| { campaign.kind(kind) }) {
+                Some(kind) => (ImString::new(format!("{} ({})", entity.name(), kind.name())), Some(kind.color())),
+                None => (ImString::new(entity.name()), None),
+            }
+        }).collect();
+        let kind_choices = kind_choices(campaign, Application::ALL_KINDS_LABEL);
+        let kind_filter = &mut self.kind_filter;
+        let tag_filter_field = &mut self.tag_filter_field;
+        let search_field = &mut self.search_field;
+        let selected_entity = self.selected_entity;
+        let selected_hit = hits.as_ref().and_then(|hits| { hits.iter().find(|hit| { Some(hit.entity) == selected_entity }) });
+        let mut current_entity = match self.selected_entity {
+            Some(selected) => entity_ids.iter().position(|id| { *id == selected }).map_or(-1, |idx| { idx as i32 }),
+            None => -1,
+        };
+        let sort = &mut self.sort;
         let create_entity_button = &mut self.create_entity_button;
+        let create_kind_button = &mut self.create_kind_button;
         let edit_entity_button = &mut self.edit_entity_button;
+        let rename_entity_button = &mut self.rename_entity_button;
+        let delete_entity_button = &mut self.delete_entity_button;
+        let save_path_field = &mut self.save_path_field;
+        let save_button = &mut self.save_button;
+        let export_path_field = &mut self.export_path_field;
+        let export_button = &mut self.export_button;
+        let status_text = &self.status_text;
         let fonts = &mut self.fonts;
         Window::new(title).size([300.0, 600.0], Condition::FirstUseEver).build(
             ui,
             || { 
                 ui.text(name_label);
-                ui.list_box(
-                    entities_label, 
-                    current_entity, 
-                    &entity_names[..], 
-                    10
-                );
+                ui.radio_button(&ImString::new(Application::SORT_ALPHABETICAL_LABEL), sort, EntitySort::Alphabetical);
+                ui.same_line(0.0);
+                ui.radio_button(&ImString::new(Application::SORT_RECENTLY_EDITED_LABEL), sort, EntitySort::RecentlyEdited);
+                ui.same_line(0.0);
+                ui.radio_button(&ImString::new(Application::SORT_CREATION_ORDER_LABEL), sort, EntitySort::CreationOrder);
+                ui.text(ImString::new(Application::KIND_FILTER_LABEL));
+                let id = ui.push_id(Application::KIND_FILTER_LABEL);
+                choice_list(ui, kind_filter, &kind_choices);
+                id.pop(ui);
+                tag_filter_field.build_gui(ui);
+                if let Some(tag_error) = &tag_error {
+                    ui.text(tag_error);
+                }
+                clicked_tag = link_list(ui, &ImString::new(Application::TAGS_IN_USE_LABEL), &all_tags);
+                search_field.build_gui(ui);
+                colored_list_box(ui, entities_label, &mut current_entity, &entity_items, 10);
+                if let Some(hit) = selected_hit {
+                    highlighted_text(ui, &hit.snippet);
+                }
                 create_entity_button.build_gui(ui);
+                ui.same_line(0.0);
+                create_kind_button.build_gui(ui);
                 edit_entity_button.build_gui(ui);
+                ui.same_line(0.0);
+                rename_entity_button.build_gui(ui);
+                ui.same_line(0.0);
+                delete_entity_button.build_gui(ui);
+                save_path_field.build_gui(ui);
+                save_button.build_gui(ui);
+                export_path_field.build_gui(ui);
+                export_button.build_gui(ui);
+                if !status_text.is_empty() {
+                    ui.text(status_text);
+                }
             }
         );       
+        if let Some(tag) = clicked_tag {
+            if !tag_filter_field.content().to_str().trim().is_empty() {
+                tag_filter_field.push_str(" ");
+            }
+            tag_filter_field.push_str(&format!("#{}", tag));
+        }
+        if save_button.pressed() {
+            let path = save_path_field.content().to_str();
+            self.status_text = match persistence::save(&self.campaign, path) {
+                Ok(()) => {
+                    let mut recent = RecentCampaigns::load_default();
+                    recent.add(path);
+                    let _ = recent.save();
+                    ImString::new(Application::SAVED_MESSAGE)
+                }
+                Err(err) => ImString::new(err.to_string()),
+            };
+        }
+        if export_button.pressed() {
+            self.status_text = match export::export_site(&self.campaign, export_path_field.content().to_str()) {
+                Ok(pages) => ImString::new(format!("{} {}", Application::EXPORTED_MESSAGE, pages)),
+                Err(err) => ImString::new(err.to_string()),
+            };
+        }
         if create_entity_button.pressed() {
             self.substates.push(Box::new(CreateEntityState::new(self.substates.len())));
         }  
-        if edit_entity_button.pressed() && *current_entity != -1 {
-            self.substates.push(Box::new(EditEntityState::new(entity_names[*current_entity as usize].to_owned(), &mut self.campaign)));
+        if create_kind_button.pressed() {
+            self.substates.push(Box::new(CreateKindState::new(self.substates.len())));
+        }
+        if current_entity != -1 {
+            self.selected_entity = Some(entity_ids[current_entity as usize]);
+        }
+        if let Some(id) = self.selected_entity {
+            if edit_entity_button.pressed() {
+                self.substates.push(Box::new(EditEntityState::new(id, &self.campaign)));
+            }
+            if rename_entity_button.pressed() {
+                self.substates.push(Box::new(RenameEntityState::new(id, &self.campaign)));
+            }
+            if delete_entity_button.pressed() {
+                self.substates.push(Box::new(DeleteEntityState::new(id, &self.campaign)));
+            }
         }
         let mut new_substates = Vec::new();
+        let mut requested_entities = Vec::new();
         for substate in self.substates {
-            let mut new_substate = substate.build_gui(ui, fonts);
+            let mut new_substate = substate.build_gui(ui, fonts, &self.campaign);
             new_substate.persist(&mut self.campaign);
+            if let Some(id) = new_substate.requested_entity() {
+                requested_entities.push(id);
+            }
+            if let Some(event) = new_substate.campaign_event() {
+                events.push(event);
+            }
+            if let Some(message) = new_substate.status_message() {
+                self.status_text = ImString::new(message);
+            }
             if !new_substate.expired() {
                 new_substates.push(new_substate);
             }
         }
+        for event in events {
+            for substate in new_substates.iter_mut() {
+                substate.handle_event(&event);
+            }
+        }
+        for id in requested_entities {
+            let already_open = new_substates.iter().any(|substate| { substate.edited_entity() == Some(id) });
+            if !already_open && self.campaign.entity(id).is_some() {
+                new_substates.push(Box::new(EditEntityState::new(id, &self.campaign)));
+            }
+        }
         self.substates = new_substates;
+        if let Some(id) = self.selected_entity {
+            if self.campaign.entity(id).is_none() {
+                self.selected_entity = None;
+            }
+        }
         self
     }
 }
 
 impl EditCampaignState {
     pub fn new(name : ImString, fonts : Fonts) -> Self {
+        let path = PathBuf::from(format!("{}.{}", name, persistence::FILE_EXTENSION));
+        EditCampaignState::from_campaign(Campaign::new(name.to_string()), path, fonts)
+    }
+
+    pub fn from_campaign(campaign : Campaign, path : PathBuf, fonts : Fonts) -> Self {
+        let export_path = path.with_extension("").display().to_string() + Application::SITE_SUFFIX;
         EditCampaignState {
             title : ImString::new(Application::EDIT_CAMPAIGN_TITLE),
-            name_label : ImString::new(format!("Campaign: {}", name.clone())),
+            name_label : ImString::new(format!("Campaign: {}", campaign.name())),
             entities_label : ImString::new(Application::ENTITIES_LABEL),
-            campaign : Campaign::new(name.to_string()),
-            current_entity : -1,
+            campaign,
+            selected_entity : None,
+            sort : EntitySort::Alphabetical,
+            kind_filter : None,
+            tag_filter_field : TextField::new(ImString::new(Application::TAG_FILTER_LABEL)),
+            search_field : TextField::new(ImString::new(Application::SEARCH_LABEL)),
             create_entity_button : Button::new(ImString::new(Application::CREATE_ENTITY_LABEL)),
+            create_kind_button : Button::new(ImString::new(Application::CREATE_KIND_LABEL)),
             edit_entity_button : Button::new(ImString::new(Application::EDIT_ENTITY_LABEL)),
+            rename_entity_button : Button::new(ImString::new(Application::RENAME_ENTITY_LABEL)),
+            delete_entity_button : Button::new(ImString::new(Application::DELETE_ENTITY_LABEL)),
+            save_path_field : TextField::with_content(ImString::new(Application::PATH_LABEL), &path.display().to_string()),
+            save_button : Button::new(ImString::new(Application::SAVE_LABEL)),
+            export_path_field : TextField::with_content(ImString::new(Application::EXPORT_PATH_LABEL), &export_path),
+            export_button : Button::new(ImString::new(Application::EXPORT_LABEL)),
+            status_text : ImString::new(""),
             substates : Vec::new(),
             fonts,
         }
     }
+
+    /// Ctrl+Z undoes and Ctrl+Y redoes the latest campaign edit, except while a text field has the keyboard and
+    /// undoes its own typing. Returns the renames this caused, for the open windows to follow.
+    fn undo_shortcut(&mut self, ui : &Ui) -> Vec<CampaignEvent> {
+        let io = ui.io();
+        if io.want_text_input || !io.key_ctrl {
+            return Vec::new();
+        }
+        let (replayed, done_message, nothing_message) = if ui.is_key_pressed(ui.key_index(Key::Z)) {
+            (self.campaign.undo(), Application::UNDONE_MESSAGE, Application::NOTHING_TO_UNDO_MESSAGE)
+        } else if ui.is_key_pressed(ui.key_index(Key::Y)) {
+            (self.campaign.redo(), Application::REDONE_MESSAGE, Application::NOTHING_TO_REDO_MESSAGE)
+        } else {
+            return Vec::new();
+        };
+        match replayed {
+            Some(replayed) => {
+                self.status_text = ImString::new(format!("{} {}", done_message, replayed.description));
+                replayed.renamed.into_iter().map(|(id, old_name, new_name)| { CampaignEvent::Renamed{ id, old_name, new_name } }).collect()
+            }
+            None => {
+                self.status_text = ImString::new(nothing_message);
+                Vec::new()
+            }
+        }
+    }
 }
 
 struct CreateEntityState {
     title : ImString,
     name_field : TextField,
+    kind : Option<KindId>,
     finish_button : Button,
     error_text : ImString,
     done : bool,
 }
 
 impl ApplicationSubstate for CreateEntityState {
-    fn build_gui(mut self : Box<Self>, ui : &Ui, _fonts : &Fonts) -> Box<dyn ApplicationSubstate> {
+    fn build_gui(mut self : Box<Self>, ui : &Ui, _fonts : &Fonts, campaign : &Campaign) -> Box<dyn ApplicationSubstate> {
         let title = &self.title;
         let name_field = &mut self.name_field;
+        let kind = &mut self.kind;
         let finish_button = &mut self.finish_button;
         let error_text = &self.error_text;
-        Window::new(title).size([200.0, 200.0], Condition::FirstUseEver).build(
+        Window::new(title).size([300.0, 300.0], Condition::FirstUseEver).build(
             ui,
             || { 
                 name_field.build_gui(ui);
+                choice_list(ui, kind, &kind_choices(campaign, Application::NO_KIND_LABEL));
                 finish_button.build_gui(ui);
                 if !error_text.is_empty() {
                     ui.text(error_text);
@@ -209,9 +521,17 @@ impl ApplicationSubstate for CreateEntityState {
 
     fn persist(&mut self, campaign : &mut Campaign) {
         if self.done {
-            if let Err(_) = campaign.new_entity(self.name_field.content().to_string()) {
-                self.done = false;
-                self.error_text = ImString::new(Application::DUPLICATE_NAME_MESSAGE);
+            match campaign.new_entity_of_kind(self.name_field.content().to_string(), self.kind) {
+                Ok(_) => {}
+                Err(NewEntityError::DuplicateName) => {
+                    self.done = false;
+                    self.error_text = ImString::new(Application::DUPLICATE_NAME_MESSAGE);
+                }
+                Err(NewEntityError::NoKind) => {
+                    self.done = false;
+                    self.kind = None;
+                    self.error_text = ImString::new(Application::MISSING_KIND_MESSAGE);
+                }
             }
         }
     }
@@ -226,6 +546,102 @@ impl CreateEntityState {
         CreateEntityState {
             title : ImString::new(format!("{}##{}", Application::CREATE_ENTITY_LABEL, id)),
             name_field : TextField::new(ImString::new(Application::NAME_LABEL)),
+            kind : None,
+            finish_button : Button::new(ImString::new(Application::FINISH_LABEL)),
+            error_text : ImString::new(""),
+            done : false,
+        }
+    }
+}
+
+fn kind_choices(campaign : &Campaign, none_label : &str) -> Vec<(Option<KindId>, ImString)> {
+    let mut choices = vec![(None, ImString::new(none_label))];
+    choices.extend(campaign.kinds().map(|kind| { (Some(kind.id()), ImString::new(kind.name())) }));
+    choices
+}
+
+struct CreateKindState {
+    title : ImString,
+    name_field : TextField,
+    color : [f32; 4],
+    fields : Vec<FieldSchema>,
+    field_name_field : TextField,
+    field_type : FieldType,
+    add_field_button : Button,
+    finish_button : Button,
+    error_text : ImString,
+    done : bool,
+}
+
+impl ApplicationSubstate for CreateKindState {
+    fn build_gui(mut self : Box<Self>, ui : &Ui, _fonts : &Fonts, _campaign : &Campaign) -> Box<dyn ApplicationSubstate> {
+        let title = &self.title;
+        let name_field = &mut self.name_field;
+        let color = &mut self.color;
+        let fields = &self.fields;
+        let field_name_field = &mut self.field_name_field;
+        let field_type = &mut self.field_type;
+        let add_field_button = &mut self.add_field_button;
+        let finish_button = &mut self.finish_button;
+        let error_text = &self.error_text;
+        let field_type_choices : Vec<(FieldType, ImString)> = FieldType::all().into_iter().map(|field_type| { 
+            (field_type, ImString::new(field_type.label())) 
+        }).collect();
+        Window::new(title).size([350.0, 400.0], Condition::FirstUseEver).build(
+            ui,
+            || { 
+                name_field.build_gui(ui);
+                ColorEdit::new(&ImString::new(Application::COLOR_LABEL), color).build(ui);
+                ui.text(ImString::new(Application::FIELDS_LABEL));
+                for field in fields {
+                    ui.bullet_text(&ImString::new(format!("{} ({})", field.name, field.field_type.label())));
+                }
+                field_name_field.build_gui(ui);
+                choice_list(ui, field_type, &field_type_choices);
+                add_field_button.build_gui(ui);
+                finish_button.build_gui(ui);
+                if !error_text.is_empty() {
+                    ui.text(error_text);
+                }                
+            }
+        );  
+        if add_field_button.pressed() && !field_name_field.content().is_empty() {
+            self.fields.push(FieldSchema::new(field_name_field.content().to_str(), *field_type));
+            self.field_name_field = TextField::new(ImString::new(Application::FIELD_NAME_LABEL));
+        }
+        if finish_button.pressed() && !name_field.content().is_empty() {
+            self.done = true;
+        } else if finish_button.pressed() {
+            self.error_text = ImString::new(Application::NON_EMPTY_NAME_MESSAGE);
+        }
+        self 
+    }
+
+    fn persist(&mut self, campaign : &mut Campaign) {
+        if self.done {
+            let name = self.name_field.content().to_str().to_string();
+            if campaign.new_kind(name, self.color, self.fields.clone()).is_err() {
+                self.done = false;
+                self.error_text = ImString::new(Application::DUPLICATE_NAME_MESSAGE);
+            }
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.done
+    }
+}
+
+impl CreateKindState {
+    pub fn new(id : usize) -> Self {
+        CreateKindState {
+            title : ImString::new(format!("{}##{}", Application::CREATE_KIND_LABEL, id)),
+            name_field : TextField::new(ImString::new(Application::NAME_LABEL)),
+            color : [1.0, 1.0, 1.0, 1.0],
+            fields : Vec::new(),
+            field_name_field : TextField::new(ImString::new(Application::FIELD_NAME_LABEL)),
+            field_type : FieldType::Text,
+            add_field_button : Button::new(ImString::new(Application::ADD_FIELD_LABEL)),
             finish_button : Button::new(ImString::new(Application::FINISH_LABEL)),
             error_text : ImString::new(""),
             done : false,
@@ -235,52 +651,431 @@ impl CreateEntityState {
 
 struct EditEntityState {
     title : ImString,
-    name : String,
+    id : EntityId,
     content : ImString,
+    kind : Option<KindId>,
+    field_editors : Vec<FieldEditor>,
+    field_name_field : TextField,
+    field_type : FieldType,
+    add_field_button : Button,
+    link_mentions_button : Button,
+    player_view : bool,
+    history_from : i32,
+    history_to : i32,
     finish_button : Button,
+    error_text : ImString,
+    requested_entity : Option<EntityId>,
     done : bool,
 }
 
 impl ApplicationSubstate for EditEntityState {
-    fn build_gui(mut self : Box<Self>, ui : &Ui, fonts: &Fonts) -> Box<dyn ApplicationSubstate> {
+    fn build_gui(mut self : Box<Self>, ui : &Ui, fonts: &Fonts, campaign : &Campaign) -> Box<dyn ApplicationSubstate> {
         const TEXT_FIELD_SIZE : [f32; 2] = [200.0, 200.0];
         let title = &self.title;
+        let id = self.id;
         let content = &mut self.content;
+        let kind = &mut self.kind;
+        let field_editors = &mut self.field_editors;
+        let field_name_field = &mut self.field_name_field;
+        let field_type = &mut self.field_type;
+        let add_field_button = &mut self.add_field_button;
+        let link_mentions_button = &mut self.link_mentions_button;
+        let player_view = &mut self.player_view;
+        let revisions = campaign.revisions(id);
+        let last_revision = (revisions.len() as i32 - 1).max(0);
+        let history_from = &mut self.history_from;
+        let history_to = &mut self.history_to;
+        *history_from = (*history_from).clamp(0, last_revision);
+        *history_to = (*history_to).clamp(0, last_revision);
+        let mut restore = false;
         let finish_button = &mut self.finish_button;
+        let error_text = &self.error_text;
+        let mut clicked_link = None;
+        let mut kind_changed = false;
+        let field_type_choices : Vec<(FieldType, ImString)> = FieldType::all().into_iter().map(|field_type| { 
+            (field_type, ImString::new(field_type.label())) 
+        }).collect();
         Window::new(title).size([800.0, 400.0], Condition::FirstUseEver).build(
             ui,
             || { 
                 ui.input_text_multiline(&ImString::new(""), content, TEXT_FIELD_SIZE).resize_buffer(true).build();
                 ui.same_line(220.0);
-                markdown(ui, content.to_string(), fonts);
+                let document = parse_document(tokenize(content.to_str()));
+                // The player view is safe to show on a shared screen: no secrets and no hints about unlinked names.
+                let (preview, mentions) = if *player_view {
+                    (Some(player_document(content.to_str())), Vec::new())
+                } else {
+                    (None, campaign.unlinked_mentions(content.to_str(), id))
+                };
+                clicked_link = markdown(ui, preview.as_ref().unwrap_or(&document), fonts, |target| { campaign.resolve(target).is_some() }, &mentions);
                 ui.set_cursor_pos([TEXT_FIELD_SIZE[0], 0.0]);
                 finish_button.build_gui(ui);
+                link_mentions_button.build_gui(ui);
+                ui.checkbox(&ImString::new(Application::PLAYER_VIEW_LABEL), player_view);
+                kind_changed = choice_list(ui, kind, &kind_choices(campaign, Application::NO_KIND_LABEL));
+                if !error_text.is_empty() {
+                    ui.text(error_text);
+                }
+                diagnostics(ui, &document.diagnostics);
+                ui.set_cursor_pos([ui.window_content_region_min()[0], TEXT_FIELD_SIZE[1] + 40.0]);
+                let sources : Vec<&str> = campaign.links_to(id).into_iter().filter_map(|source| {
+                    campaign.entity(source).map(|entity| { entity.name() })
+                }).collect();
+                let linked_from = link_list(ui, &ImString::new(Application::LINKED_FROM_LABEL), &sources);
+                let links_to = link_list(ui, &ImString::new(Application::LINKS_TO_LABEL), &campaign.links_from(id));
+                clicked_link = clicked_link.take().or(linked_from).or(links_to);
+                ui.text(ImString::new(Application::FIELDS_LABEL));
+                for (idx, editor) in field_editors.iter_mut().enumerate() {
+                    let id = ui.push_id(idx as i32);
+                    let clicked_reference = editor.build_gui(ui, |target| { campaign.resolve(target).is_some() });
+                    id.pop(ui);
+                    clicked_link = clicked_link.take().or(clicked_reference);
+                }
+                field_name_field.build_gui(ui);
+                choice_list(ui, field_type, &field_type_choices);
+                add_field_button.build_gui(ui);
+                if CollapsingHeader::new(&ImString::new(Application::HISTORY_LABEL)).build(ui) {
+                    if revisions.is_empty() {
+                        ui.text(ImString::new(Application::NO_REVISIONS_MESSAGE));
+                    } else {
+                        // Revisions made in the same minute have the same label, so the index keeps their ids apart.
+                        let labels : Vec<(ImString, Option<[f32; 4]>)> = revisions.iter().enumerate().map(|(idx, revision)| {
+                            (ImString::new(format!("{}##{}", revision.label(), idx)), None)
+                        }).collect();
+                        colored_list_box(ui, &ImString::new(Application::CHANGES_FROM_LABEL), history_from, &labels, 5);
+                        restore = button(ui, &ImString::new(Application::RESTORE_REVISION_LABEL));
+                        colored_list_box(ui, &ImString::new(Application::CHANGES_TO_LABEL), history_to, &labels, 5);
+                        diff_text(ui, &revision_diff(&revisions[*history_from as usize], &revisions[*history_to as usize]));
+                    }
+                }
             }
         );  
+        if kind_changed {
+            let fields : Vec<Field> = self.field_editors.iter().map(|editor| { editor.field() }).collect();
+            for field in missing_schema_fields(campaign, self.kind, &fields) {
+                self.field_editors.push(FieldEditor::new(&field));
+            }
+        }
+        if self.add_field_button.pressed() && !self.field_name_field.content().is_empty() {
+            let field = Field::new(self.field_name_field.content().to_str(), FieldValue::default_for(self.field_type));
+            self.field_editors.push(FieldEditor::new(&field));
+            self.field_name_field = TextField::new(ImString::new(Application::FIELD_NAME_LABEL));
+        }
+        if self.link_mentions_button.pressed() {
+            let mut text = self.content.to_str().to_string();
+            let mentions = campaign.unlinked_mentions(&text, self.id);
+            if link_mentions(&mut text, &mentions) > 0 {
+                self.content = ImString::new(text);
+            }
+        }
+        // Restoring only fills in the editor, so the old revision can still be changed before it is committed.
+        if restore {
+            if let Some(revision) = campaign.revisions(self.id).get(self.history_from as usize) {
+                self.content = ImString::new(revision.content().text.clone());
+                self.field_editors = revision.content().fields.iter().map(FieldEditor::new).collect();
+            }
+        }
         if self.finish_button.pressed() {
             self.done = true;
         }
+        self.requested_entity = clicked_link.and_then(|target| { campaign.resolve(&target) });
         self
     }
 
     fn persist(&mut self, campaign : &mut Campaign) {
         if self.done {
-            campaign.update_entity_content(&self.name, EntityContent{ text : self.content.to_string() }).unwrap();
+            let content = EntityContent {
+                text : self.content.to_string(),
+                fields : self.field_editors.iter().map(|editor| { editor.field() }).collect(),
+            };
+            match campaign.update_entity(self.id, self.kind, content) {
+                Ok(()) => {}
+                Err(UpdateEntityError::NoEntity) => {
+                    self.done = false;
+                    self.error_text = ImString::new(Application::MISSING_ENTITY_MESSAGE);
+                }
+                Err(UpdateEntityError::NoKind) => {
+                    self.done = false;
+                    self.kind = None;
+                    self.error_text = ImString::new(Application::MISSING_KIND_MESSAGE);
+                }
+            }
         }
     }
 
     fn expired(&self) -> bool {
         self.done
     }
+
+    fn edited_entity(&self) -> Option<EntityId> {
+        Some(self.id)
+    }
+
+    fn requested_entity(&mut self) -> Option<EntityId> {
+        self.requested_entity.take()
+    }
+
+    fn handle_event(&mut self, event : &CampaignEvent) {
+        let mut text = self.content.to_str().to_string();
+        let count = match event {
+            CampaignEvent::Renamed{ id, old_name, new_name } => {
+                if *id == self.id {
+                    self.title = EditEntityState::title(self.id, new_name);
+                }
+                let old_key = name_key(old_name);
+                for editor in self.field_editors.iter_mut() {
+                    editor.retarget(&old_key, new_name);
+                }
+                retarget_links(&mut text, &old_key, new_name)
+            }
+            CampaignEvent::Deleted{ name, dangling_links : DanglingLinks::Unlink } => {
+                let key = name_key(name);
+                for editor in self.field_editors.iter_mut() {
+                    editor.retarget(&key, "");
+                }
+                clear_links(&mut text, &key)
+            }
+            CampaignEvent::Deleted{ .. } => return,
+        };
+        if count > 0 {
+            self.content = ImString::new(text);
+        }
+    }
 }
 
 impl EditEntityState {
-    pub fn new(name : ImString, campaign : &Campaign) -> Self {
+    pub fn new(id : EntityId, campaign : &Campaign) -> Self {
+        let entity = campaign.entity(id).unwrap();
+        let mut fields = entity.content().fields.clone();
+        fields.extend(missing_schema_fields(campaign, entity.kind(), &fields));
+        // Selections out of range, as with fewer than two revisions, are clamped while building the window.
+        let revision_count = campaign.revisions(id).len() as i32;
         EditEntityState {
-            title : ImString::new(format!("{}: {}", Application::EDIT_ENTITY_LABEL, name)),
-            name : name.to_string(),
-            content : ImString::new(campaign.entities().get(name.to_str()).unwrap().content().text.clone()),
+            title : EditEntityState::title(id, entity.name()),
+            id,
+            content : ImString::new(entity.content().text.clone()),
+            kind : entity.kind(),
+            field_editors : fields.iter().map(FieldEditor::new).collect(),
+            field_name_field : TextField::new(ImString::new(Application::FIELD_NAME_LABEL)),
+            field_type : FieldType::Text,
+            add_field_button : Button::new(ImString::new(Application::ADD_FIELD_LABEL)),
+            link_mentions_button : Button::new(ImString::new(Application::LINK_MENTIONS_LABEL)),
+            player_view : false,
+            history_from : revision_count - 2,
+            history_to : revision_count - 1,
             finish_button : Button::new(ImString::new(Application::FINISH_LABEL)),
+            error_text : ImString::new(""),
+            requested_entity : None,
+            done : false,
+        }
+    }
+
+    fn title(id : EntityId, name : &str) -> ImString {
+        ImString::new(format!("{}: {}###{}{}", Application::EDIT_ENTITY_LABEL, name, Application::EDIT_ENTITY_LABEL, id))
+    }
+}
+
+/// The fields of `kind`'s schema that `fields` lacks, with default values.
+fn missing_schema_fields(campaign : &Campaign, kind : Option<KindId>, fields : &[Field]) -> Vec<Field> {
+    match kind.and_then(|kind| { campaign.kind(kind) }) {
+        Some(kind) => kind.fields().iter()
+            .filter(|schema| { !fields.iter().any(|field| { field.name == schema.name }) })
+            .map(|schema| { Field::new(&schema.name, FieldValue::default_for(schema.field_type)) })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+struct RenameEntityState {
+    title : ImString,
+    id : EntityId,
+    name : String,
+    name_field : TextField,
+    finish_button : Button,
+    error_text : ImString,
+    event : Option<CampaignEvent>,
+    status_message : Option<String>,
+    done : bool,
+}
+
+impl ApplicationSubstate for RenameEntityState {
+    fn build_gui(mut self : Box<Self>, ui : &Ui, _fonts : &Fonts, _campaign : &Campaign) -> Box<dyn ApplicationSubstate> {
+        let title = &self.title;
+        let name_field = &mut self.name_field;
+        let finish_button = &mut self.finish_button;
+        let error_text = &self.error_text;
+        Window::new(title).size([300.0, 200.0], Condition::FirstUseEver).build(
+            ui,
+            || { 
+                name_field.build_gui(ui);
+                finish_button.build_gui(ui);
+                if !error_text.is_empty() {
+                    ui.text(error_text);
+                }                
+            }
+        );  
+        if finish_button.pressed() && !name_field.content().is_empty() {
+            self.done = true;
+        } else if finish_button.pressed() {
+            self.error_text = ImString::new(Application::NON_EMPTY_NAME_MESSAGE);
+        }
+        self 
+    }
+
+    fn persist(&mut self, campaign : &mut Campaign) {
+        if self.done {
+            let new_name = self.name_field.content().to_str().to_string();
+            match campaign.rename_entity(self.id, new_name.clone()) {
+                Ok(count) => {
+                    self.status_message = Some(format!("Renamed {} to {}, {} references updated", self.name, new_name, count));
+                    self.event = Some(CampaignEvent::Renamed{ id : self.id, old_name : self.name.clone(), new_name });
+                }
+                Err(RenameEntityError::DuplicateName) => {
+                    self.done = false;
+                    self.error_text = ImString::new(Application::DUPLICATE_NAME_MESSAGE);
+                }
+                Err(RenameEntityError::NoEntity) => {
+                    self.done = false;
+                    self.error_text = ImString::new(Application::MISSING_ENTITY_MESSAGE);
+                }
+            }
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.done
+    }
+
+    fn campaign_event(&mut self) -> Option<CampaignEvent> {
+        self.event.take()
+    }
+
+    fn handle_event(&mut self, event : &CampaignEvent) {
+        if let CampaignEvent::Renamed{ id, new_name, .. } = event {
+            if *id == self.id {
+                self.name = new_name.clone();
+            }
+        }
+    }
+
+    fn status_message(&mut self) -> Option<String> {
+        self.status_message.take()
+    }
+}
+
+impl RenameEntityState {
+    pub fn new(id : EntityId, campaign : &Campaign) -> Self {
+        let name = campaign.entity(id).unwrap().name().to_string();
+        RenameEntityState {
+            title : ImString::new(format!("{}: {}###{}{}", Application::RENAME_ENTITY_LABEL, name, Application::RENAME_ENTITY_LABEL, id)),
+            id,
+            name_field : TextField::with_content(ImString::new(Application::NAME_LABEL), &name),
+            name,
+            finish_button : Button::new(ImString::new(Application::FINISH_LABEL)),
+            error_text : ImString::new(""),
+            event : None,
+            status_message : None,
+            done : false,
+        }
+    }
+}
+
+struct DeleteEntityState {
+    title : ImString,
+    id : EntityId,
+    name : String,
+    keep_button : Button,
+    unlink_button : Button,
+    cancel_button : Button,
+    choice : Option<DanglingLinks>,
+    event : Option<CampaignEvent>,
+    status_message : Option<String>,
+    done : bool,
+}
+
+impl ApplicationSubstate for DeleteEntityState {
+    fn build_gui(mut self : Box<Self>, ui : &Ui, _fonts : &Fonts, campaign : &Campaign) -> Box<dyn ApplicationSubstate> {
+        let title = &self.title;
+        let id = self.id;
+        let keep_button = &mut self.keep_button;
+        let unlink_button = &mut self.unlink_button;
+        let cancel_button = &mut self.cancel_button;
+        Window::new(title).size([400.0, 300.0], Condition::FirstUseEver).build(
+            ui,
+            || { 
+                let referencing = campaign.deletion_preview(id);
+                if referencing.is_empty() {
+                    ui.text(ImString::new(Application::NO_REFERENCES_MESSAGE));
+                } else {
+                    ui.text(ImString::new(Application::DANGLING_REFERENCES_MESSAGE));
+                    for source in referencing.into_iter().filter_map(|source| { campaign.entity(source) }) {
+                        ui.bullet_text(&ImString::new(source.name()));
+                    }
+                }
+                keep_button.build_gui(ui);
+                unlink_button.build_gui(ui);
+                cancel_button.build_gui(ui);
+            }
+        );  
+        if self.keep_button.pressed() {
+            self.choice = Some(DanglingLinks::Keep);
+        } else if self.unlink_button.pressed() {
+            self.choice = Some(DanglingLinks::Unlink);
+        } else if self.cancel_button.pressed() {
+            self.done = true;
+        }
+        self 
+    }
+
+    fn persist(&mut self, campaign : &mut Campaign) {
+        if let Some(dangling_links) = self.choice.take() {
+            self.done = true;
+            match campaign.delete_entity(self.id, dangling_links) {
+                Ok(count) => {
+                    self.status_message = Some(format!("Deleted {}, {} references unlinked", self.name, count));
+                    self.event = Some(CampaignEvent::Deleted{ name : self.name.clone(), dangling_links });
+                }
+                Err(_) => {
+                    self.status_message = Some(Application::MISSING_ENTITY_MESSAGE.to_string());
+                }
+            }
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.done
+    }
+
+    fn campaign_event(&mut self) -> Option<CampaignEvent> {
+        self.event.take()
+    }
+
+    fn handle_event(&mut self, event : &CampaignEvent) {
+        if let CampaignEvent::Renamed{ id, new_name, .. } = event {
+            if *id == self.id {
+                self.name = new_name.clone();
+            }
+        }
+    }
+
+    fn status_message(&mut self) -> Option<String> {
+        self.status_message.take()
+    }
+}
+
+impl DeleteEntityState {
+    pub fn new(id : EntityId, campaign : &Campaign) -> Self {
+        let name = campaign.entity(id).unwrap().name().to_string();
+        DeleteEntityState {
+            title : ImString::new(format!("{}: {}###{}{}", Application::DELETE_ENTITY_LABEL, name, Application::DELETE_ENTITY_LABEL, id)),
+            id,
+            name,
+            keep_button : Button::new(ImString::new(Application::KEEP_LINKS_LABEL)),
+            unlink_button : Button::new(ImString::new(Application::UNLINK_LABEL)),
+            cancel_button : Button::new(ImString::new(Application::CANCEL_LABEL)),
+            choice : None,
+            event : None,
+            status_message : None,
             done : false,
         }
     }
@@ -301,6 +1096,53 @@ impl Application {
     pub const CREATE_ENTITY_LABEL : &'static str = "Create Entity";
     pub const DUPLICATE_NAME_MESSAGE : &'static str = "Duplicate names are not allowed";
     pub const EDIT_ENTITY_LABEL : &'static str = "Edit Entity";
+    pub const PATH_LABEL : &'static str = "Path";
+    pub const SAVE_LABEL : &'static str = "Save campaign";
+    pub const SAVED_MESSAGE : &'static str = "Campaign saved";
+    pub const EXPORT_PATH_LABEL : &'static str = "Site folder";
+    pub const EXPORT_LABEL : &'static str = "Export site";
+    pub const EXPORTED_MESSAGE : &'static str = "Pages written:";
+    pub const SITE_SUFFIX : &'static str = "_site";
+    pub const OPEN_CAMPAIGN_TITLE : &'static str = "Open Campaign";
+    pub const OPEN_LABEL : &'static str = "Open";
+    pub const SORT_ALPHABETICAL_LABEL : &'static str = "A-Z";
+    pub const SORT_RECENTLY_EDITED_LABEL : &'static str = "Recent";
+    pub const SORT_CREATION_ORDER_LABEL : &'static str = "Created";
+    pub const NO_KIND_LABEL : &'static str = "No kind";
+    pub const ALL_KINDS_LABEL : &'static str = "All";
+    pub const KIND_FILTER_LABEL : &'static str = "Kind:";
+    pub const TAG_FILTER_LABEL : &'static str = "Tags (#a #b | #c)";
+    pub const TAGS_IN_USE_LABEL : &'static str = "Tags in use:";
+    pub const INVALID_TAG_MESSAGE : &'static str = "Not a tag:";
+    pub const EMPTY_TAG_ALTERNATIVE_MESSAGE : &'static str = "Every 'or' needs tags on both sides";
+    pub const SEARCH_LABEL : &'static str = "Search";
+    pub const CREATE_KIND_LABEL : &'static str = "Create Kind";
+    pub const COLOR_LABEL : &'static str = "Colour";
+    pub const FIELDS_LABEL : &'static str = "Fields:";
+    pub const FIELD_NAME_LABEL : &'static str = "Field name";
+    pub const ADD_FIELD_LABEL : &'static str = "Add field";
+    pub const MISSING_KIND_MESSAGE : &'static str = "The kind no longer exists";
+    pub const LINKED_FROM_LABEL : &'static str = "Linked from:";
+    pub const LINKS_TO_LABEL : &'static str = "Links to:";
+    pub const LINK_MENTIONS_LABEL : &'static str = "Link all mentions";
+    pub const PLAYER_VIEW_LABEL : &'static str = "Player view";
+    pub const HISTORY_LABEL : &'static str = "History";
+    pub const NO_REVISIONS_MESSAGE : &'static str = "No revisions yet.";
+    pub const CHANGES_FROM_LABEL : &'static str = "Changes from";
+    pub const CHANGES_TO_LABEL : &'static str = "to";
+    pub const RESTORE_REVISION_LABEL : &'static str = "Restore this revision";
+    pub const RENAME_ENTITY_LABEL : &'static str = "Rename Entity";
+    pub const MISSING_ENTITY_MESSAGE : &'static str = "The entity no longer exists";
+    pub const DELETE_ENTITY_LABEL : &'static str = "Delete Entity";
+    pub const KEEP_LINKS_LABEL : &'static str = "Delete and keep dangling links";
+    pub const UNLINK_LABEL : &'static str = "Delete and unlink references";
+    pub const CANCEL_LABEL : &'static str = "Cancel";
+    pub const NO_REFERENCES_MESSAGE : &'static str = "No other entity links to this one.";
+    pub const DANGLING_REFERENCES_MESSAGE : &'static str = "These entities still link to this one:";
+    pub const UNDONE_MESSAGE : &'static str = "Undone:";
+    pub const REDONE_MESSAGE : &'static str = "Redone:";
+    pub const NOTHING_TO_UNDO_MESSAGE : &'static str = "Nothing to undo";
+    pub const NOTHING_TO_REDO_MESSAGE : &'static str = "Nothing to redo";
 
     pub fn new(fonts : Fonts) -> Self {
         Application {
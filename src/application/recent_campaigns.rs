@@ -0,0 +1,101 @@
+use std::path::{ Path, PathBuf };
+
+pub struct RecentCampaigns {
+    store : Option<PathBuf>,
+    paths : Vec<PathBuf>,
+}
+
+impl RecentCampaigns {
+    pub const MAX_ENTRIES : usize = 10;
+
+    pub fn load_default() -> Self {
+        match dirs::config_dir() {
+            Some(dir) => RecentCampaigns::load_from(dir.join("gm-unleashed").join("recent_campaigns")),
+            None => RecentCampaigns { store : None, paths : Vec::new() },
+        }
+    }
+
+    pub fn load_from(store : PathBuf) -> Self {
+        let paths = match std::fs::read_to_string(&store) {
+            Ok(data) => data.lines()
+                .filter(|line| { !line.trim().is_empty() })
+                .map(PathBuf::from)
+                .take(RecentCampaigns::MAX_ENTRIES)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        RecentCampaigns {
+            store : Some(store),
+            paths,
+        }
+    }
+
+    pub fn add<P>(&mut self, path : P) 
+        where P : AsRef<Path>
+    {
+        let path = path.as_ref().to_path_buf();
+        self.paths.retain(|existing| { existing != &path });
+        self.paths.insert(0, path);
+        self.paths.truncate(RecentCampaigns::MAX_ENTRIES);
+    }
+
+    pub fn remove<P>(&mut self, path : P) 
+        where P : AsRef<Path>
+    {
+        let path = path.as_ref();
+        self.paths.retain(|existing| { existing != path });
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        if let Some(store) = &self.store {
+            if let Some(dir) = store.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let data : Vec<String> = self.paths.iter().map(|path| { path.to_string_lossy().into_owned() }).collect();
+            std::fs::write(store, data.join("\n"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn paths(&self) -> &[PathBuf] { &self.paths }
+}
+
+#[cfg(test)]
+mod recent_campaigns_tests {
+    use super::*;
+    fn temp_store(name : &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gm_unleashed_recent_{}_{}", name, std::process::id()))
+    }
+    #[test]
+    fn most_recent_comes_first() {
+        let mut recent = RecentCampaigns::load_from(temp_store("order"));
+        recent.add("a");
+        recent.add("b");
+        recent.add("a");
+        assert_eq!(recent.paths(), &[PathBuf::from("a"), PathBuf::from("b")]);
+    }
+    #[test]
+    fn number_of_entries_is_limited() {
+        let mut recent = RecentCampaigns::load_from(temp_store("limit"));
+        for idx in 0..RecentCampaigns::MAX_ENTRIES + 5 {
+            recent.add(idx.to_string());
+        }
+        assert_eq!(recent.paths().len(), RecentCampaigns::MAX_ENTRIES);
+    }
+    #[test]
+    fn entries_are_persisted() {
+        let store = temp_store("persist");
+        let mut recent = RecentCampaigns::load_from(store.clone());
+        recent.add("a");
+        recent.add("b");
+        recent.save().unwrap();
+        let reloaded = RecentCampaigns::load_from(store.clone());
+        std::fs::remove_file(&store).unwrap();
+        assert_eq!(reloaded.paths(), &[PathBuf::from("b"), PathBuf::from("a")]);
+    }
+    #[test]
+    fn missing_store_is_empty() {
+        assert_eq!(RecentCampaigns::load_from(temp_store("missing")).paths().len(), 0);
+    }
+}
@@ -1,6 +1,7 @@
 use imgui::*;
 use gm_unleashed_md::{ *, Style };
 use super::{ Fonts, FontStyle };
+use super::campaign::{ Field, FieldValue, Mention, Snippet, DiffSpan, DiffKind, name_key };
 
 pub struct Button {
     label : ImString,
@@ -20,11 +21,19 @@ impl TextField {
         }
     }
 
+    pub fn with_content(label : ImString, content : &str) -> Self {
+        let mut field = TextField::new(label);
+        field.content.push_str(content);
+        field
+    }
+
     pub fn build_gui(&mut self, ui : &Ui) {
         ui.input_text(&self.label, &mut self.content).build();
     }
 
     pub fn content(&self) -> &ImStr { &self.content }
+
+    pub fn push_str(&mut self, text : &str) { self.content.push_str(text); }
 }
 
 impl Button {
@@ -48,66 +57,397 @@ pub fn button(ui : &Ui, label : &ImStr) -> bool {
     ui.button(label, size)
 }
 
-struct ActiveStyle {
-    end : usize,
-    style : Style,
+pub fn choice_list<T>(ui : &Ui, value : &mut T, choices : &[(T, ImString)]) -> bool
+    where T : Copy + PartialEq
+{
+    let mut changed = false;
+    for (choice, label) in choices {
+        changed |= ui.radio_button(label, value, *choice);
+    }
+    changed
+}
+
+pub fn colored_list_box(ui : &Ui, label : &ImStr, current : &mut i32, items : &[(ImString, Option<[f32; 4]>)], height_in_items : usize) -> bool {
+    let mut changed = false;
+    ui.text(label);
+    let height = ui.text_line_height_with_spacing() * height_in_items as f32;
+    ChildWindow::new(label.to_str()).size([0.0, height]).border(true).build(ui, || {
+        for (idx, (item, color)) in items.iter().enumerate() {
+            let color = color.map(|color| { ui.push_style_color(StyleColor::Text, color) });
+            if Selectable::new(item).selected(*current == idx as i32).build(ui) {
+                *current = idx as i32;
+                changed = true;
+            }
+            if let Some(color) = color {
+                color.pop(ui);
+            }
+        }
+    });
+    changed
+}
+
+pub fn highlighted_text(ui : &Ui, snippet : &Snippet) {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for range in &snippet.highlights {
+        segments.push((&snippet.text[start..range.start], None));
+        segments.push((&snippet.text[range.clone()], Some(HIGHLIGHT_COLOR)));
+        start = range.end;
+    }
+    segments.push((&snippet.text[start..], None));
+    let mut first = true;
+    for (segment, color) in segments.into_iter().filter(|(segment, _)| { !segment.is_empty() }) {
+        if !first {
+            ui.same_line_with_spacing(0.0, 0.0);
+        }
+        first = false;
+        match color {
+            Some(color) => ui.text_colored(color, ImString::new(segment)),
+            None => ui.text(ImString::new(segment)),
+        }
+    }
+}
+
+pub fn diff_text(ui : &Ui, spans : &[DiffSpan]) {
+    let [line_start, _] = ui.cursor_pos();
+    let mut line_end : Option<f32> = None;
+    for span in spans {
+        let color = match span.kind {
+            DiffKind::Same => None,
+            DiffKind::Removed => Some(REMOVED_COLOR),
+            DiffKind::Added => Some(ADDED_COLOR),
+        };
+        for (idx, line) in span.text.split('\n').enumerate() {
+            if idx > 0 {
+                if line_end.is_none() {
+                    ui.new_line();
+                }
+                line_end = None;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            match line_end {
+                Some(line_end) => ui.same_line(line_end),
+                None => {
+                    let [_, y] = ui.cursor_pos();
+                    ui.set_cursor_pos([line_start, y]);
+                }
+            }
+            let color = color.map(|color| { ui.push_style_color(StyleColor::Text, color) });
+            wrapped_text(ui, &ImString::new(line), line_start);
+            if let Some(color) = color {
+                color.pop(ui);
+            }
+            line_end = Some(ui.item_rect_max()[0] - ui.window_pos()[0]);
+        }
+    }
+}
+
+pub fn link_list(ui : &Ui, label : &ImStr, names : &[&str]) -> Option<String> {
+    let mut clicked = None;
+    let id = ui.push_id(label);
+    ui.text(label);
+    for name in names {
+        if button(ui, &ImString::new(*name)) {
+            clicked = Some(name.to_string());
+        }
+    }
+    id.pop(ui);
+    clicked
+}
+
+pub struct FieldEditor {
+    label : ImString,
+    value : FieldValue,
+    buffer : ImString,
+}
+
+impl FieldEditor {
+    pub fn new(field : &Field) -> Self {
+        let buffer = match &field.value {
+            FieldValue::Text(text) | FieldValue::EntityReference(text) => text.clone(),
+            FieldValue::List(items) => items.join("\n"),
+            FieldValue::Number(number) => number.to_string(),
+            FieldValue::Boolean(_) => String::new(),
+        };
+        FieldEditor {
+            label : ImString::new(field.name.clone()),
+            value : field.value.clone(),
+            buffer : ImString::new(buffer),
+        }
+    }
+
+    pub fn build_gui<F>(&mut self, ui : &Ui, link_exists : F) -> Option<String>
+        where F : Fn(&str) -> bool
+    {
+        const LIST_FIELD_SIZE : [f32; 2] = [200.0, 60.0];
+        let mut clicked_link = None;
+        match &mut self.value {
+            FieldValue::Text(_) => {
+                ui.input_text(&self.label, &mut self.buffer).resize_buffer(true).build();
+            }
+            // Edited as text, as `input_float` would round the number to an f32.
+            FieldValue::Number(_) => {
+                ui.input_text(&self.label, &mut self.buffer).chars_decimal(true).resize_buffer(true).build();
+            }
+            FieldValue::Boolean(flag) => {
+                ui.checkbox(&self.label, flag);
+            }
+            FieldValue::EntityReference(_) => {
+                let target = self.buffer.to_str().trim().to_string();
+                let color = if target.is_empty() || link_exists(&target) { None } else { Some(BROKEN_LINK_COLOR) };
+                let color = color.map(|color| { ui.push_style_color(StyleColor::Text, color) });
+                ui.input_text(&self.label, &mut self.buffer).resize_buffer(true).build();
+                if let Some(color) = color {
+                    color.pop(ui);
+                }
+                if !target.is_empty() && link_exists(&target) {
+                    ui.same_line(0.0);
+                    let id = ui.push_id(&self.label);
+                    if button(ui, &ImString::new(target.clone())) {
+                        clicked_link = Some(target);
+                    }
+                    id.pop(ui);
+                }
+            }
+            FieldValue::List(_) => {
+                ui.input_text_multiline(&self.label, &mut self.buffer, LIST_FIELD_SIZE).resize_buffer(true).build();
+            }
+        }
+        clicked_link
+    }
+
+    pub fn field(&self) -> Field {
+        let text = self.buffer.to_str();
+        let value = match &self.value {
+            FieldValue::Text(_) => FieldValue::Text(text.to_string()),
+            FieldValue::EntityReference(_) => FieldValue::EntityReference(text.trim().to_string()),
+            FieldValue::List(_) => FieldValue::List(text.lines().map(str::trim).filter(|item| { !item.is_empty() }).map(String::from).collect()),
+            FieldValue::Number(number) => FieldValue::Number(text.trim().parse().unwrap_or(*number)),
+            value => value.clone(),
+        };
+        Field::new(self.label.to_str(), value)
+    }
+
+    pub fn retarget(&mut self, old_key : &str, new_name : &str) {
+        if let FieldValue::EntityReference(_) = self.value {
+            if name_key(self.buffer.to_str()) == old_key {
+                self.buffer = ImString::new(new_name);
+            }
+        }
+    }
+}
+
+const LINK_COLOR : [f32; 4] = [0.35, 0.6, 1.0, 1.0];
+const BROKEN_LINK_COLOR : [f32; 4] = [0.9, 0.3, 0.3, 1.0];
+const MENTION_COLOR : [f32; 4] = [0.55, 0.7, 0.85, 1.0];
+const TAG_COLOR : [f32; 4] = [0.4, 0.8, 0.5, 1.0];
+const HIGHLIGHT_COLOR : [f32; 4] = [1.0, 0.85, 0.3, 1.0];
+const ADDED_COLOR : [f32; 4] = [0.4, 0.85, 0.4, 1.0];
+const REMOVED_COLOR : [f32; 4] = [0.95, 0.4, 0.4, 1.0];
+const QUOTE_COLOR : [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+const SECRET_BACKGROUND : [f32; 4] = [0.6, 0.2, 0.5, 0.3];
+const LIST_INDENT : f32 = 30.0;
+const LIST_MARKER_WIDTH : f32 = 30.0;
+const LIST_MARKER_SPACING : f32 = 8.0;
+const QUOTE_INDENT : f32 = 20.0;
+const DIAGNOSTIC_COLOR : [f32; 4] = [0.9, 0.7, 0.2, 1.0];
+
+pub fn diagnostics(ui : &Ui, diagnostics : &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        ui.text_colored(DIAGNOSTIC_COLOR, ImString::new(diagnostic.to_string()));
+    }
 }
 
-pub fn markdown<S>(ui : &Ui, raw_md : S, fonts : &Fonts)
-    where S : Into<String> 
+pub fn markdown<F>(ui : &Ui, document : &Document, fonts : &Fonts, link_exists : F, mentions : &[Mention]) -> Option<String>
+    where F : Fn(&str) -> bool
 {
     let [offset, _] = ui.cursor_pos();
-    let md = parse(tokenize(raw_md.into()));
-    let mut breaks = md.breaks.into_iter().peekable();
-    let mut styles = md.styles.into_iter().peekable();
-    let mut active_font_style = FontStyle::Normal;
     let outer_font = ui.push_font(*fonts.get(&FontStyle::Normal));
-    let mut active_styles = Vec::new();
-    for (idx, text) in md.text.into_iter().map(|s| {ImString::new(s)}).enumerate() {
-        while let Some(style) = styles.peek() {
-            if style.span.start == idx {
-                let style = styles.next().unwrap();
-                active_font_style = active_font_style + style.style.clone();
-                active_styles.push(ActiveStyle {
-                    end : style.span.end,
-                    style : style.style,
-                });
-            } else {
-                break
-            }
-        }
-        let font = ui.push_font(*fonts.get(&active_font_style));
-        wrapped_text(ui, &text, offset);
+    let mut renderer = MarkdownRenderer {
+        ui,
+        fonts,
+        link_exists,
+        mentions,
+        clicked_link : None,
+        line_start : offset,
+        line_end : None,
+        in_secret : false,
+    };
+    renderer.blocks(&document.blocks, offset);
+    outer_font.pop(ui);
+    renderer.clicked_link
+}
+
+struct MarkdownRenderer<'a, 'ui, F> {
+    ui : &'a Ui<'ui>,
+    fonts : &'a Fonts,
+    link_exists : F,
+    mentions : &'a [Mention],
+    clicked_link : Option<String>,
+    line_start : f32,
+    line_end : Option<f32>,
+    in_secret : bool,
+}
+
+impl<'a, 'ui, F> MarkdownRenderer<'a, 'ui, F>
+    where F : Fn(&str) -> bool
+{
+    fn blocks(&mut self, blocks : &[Block], indent : f32) {
+        for (idx, block) in blocks.iter().enumerate() {
+            if idx > 0 {
+                let [x, y] = self.ui.cursor_pos();
+                self.ui.set_cursor_pos([x, y + self.ui.text_line_height_with_spacing() * 0.5]);
+            }
+            match &block.kind {
+                BlockKind::Paragraph(content) => self.line(indent, content, FontStyle::Normal),
+                BlockKind::Heading{ level, content } => self.line(indent, content, FontStyle::Heading(*level)),
+                BlockKind::List(items) => {
+                    for item in items {
+                        let text_start = list_item_marker(self.ui, indent, item.number);
+                        self.line_end = Some(text_start);
+                        self.line(text_start, &item.content, FontStyle::Normal);
+                        if item.content.is_empty() && item.number.is_none() {
+                            self.ui.new_line();
+                        }
+                        self.blocks(&item.children, indent + LIST_INDENT);
+                    }
+                }
+                BlockKind::Quote(blocks) => {
+                    let color = self.ui.push_style_color(StyleColor::Text, QUOTE_COLOR);
+                    self.blocks(blocks, indent + QUOTE_INDENT);
+                    color.pop(self.ui);
+                }
+                BlockKind::Secret(blocks) => {
+                    let ui = self.ui;
+                    let [x, _] = ui.window_pos();
+                    let top = ui.cursor_screen_pos()[1];
+                    self.blocks(blocks, indent + QUOTE_INDENT);
+                    let bottom = ui.cursor_screen_pos()[1];
+                    ui.get_window_draw_list().add_rect([x + indent, top], [x + ui.window_size()[0], bottom], SECRET_BACKGROUND)
+                        .filled(true)
+                        .build();
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, line_start : f32, content : &[Inline], font_style : FontStyle) {
+        self.line_start = line_start;
+        self.inlines(content, font_style, None);
+        self.line_end = None;
+    }
+
+    fn inlines(&mut self, inlines : &[Inline], font_style : FontStyle, link_target : Option<&str>) {
+        for inline in inlines {
+            match &inline.kind {
+                InlineKind::Text(text) if link_target.is_none() => self.mentioned_text(text, &inline.range, font_style),
+                InlineKind::Text(text) => self.text(text, font_style, link_target),
+                InlineKind::Emphasis(content) => self.inlines(content, font_style + Style::Italic, link_target),
+                InlineKind::Strong(content) => self.inlines(content, font_style + Style::Bold, link_target),
+                InlineKind::Link{ target, content } => self.inlines(content, font_style, Some(target)),
+                InlineKind::WikiLink{ target, text } => self.text(text.as_ref().unwrap_or(target), font_style, Some(target)),
+                InlineKind::Code(code) => self.text(code, FontStyle::Code, link_target),
+                InlineKind::Tag(name) if link_target.is_none() => self.colored_text(&format!("#{}", name), font_style, Some(TAG_COLOR), None),
+                InlineKind::Tag(name) => self.text(&format!("#{}", name), font_style, link_target),
+                InlineKind::Secret(content) => {
+                    let in_secret = std::mem::replace(&mut self.in_secret, true);
+                    self.inlines(content, font_style, link_target);
+                    self.in_secret = in_secret;
+                }
+                InlineKind::LineBreak => self.line_end = None,
+            }
+        }
+    }
+
+    fn mentioned_text(&mut self, text : &str, range : &SourceRange, font_style : FontStyle) {
+        let mentions = self.mentions;
+        let mut start = 0;
+        // Mentions are found in the source, so they can only be placed in text that has no escapes.
+        if range.len() == text.len() {
+            for mention in mentions.iter().filter(|mention| { range.start <= mention.range.start && mention.range.end <= range.end }) {
+                let (mention_start, mention_end) = (mention.range.start - range.start, mention.range.end - range.start);
+                if mention_start > start {
+                    self.text(&text[start..mention_start], font_style, None);
+                }
+                self.colored_text(&text[mention_start..mention_end], font_style, Some(MENTION_COLOR), Some(&mention.name));
+                start = mention_end;
+            }
+        }
+        if start < text.len() {
+            self.text(&text[start..], font_style, None);
+        }
+    }
+
+    fn text(&mut self, text : &str, font_style : FontStyle, link_target : Option<&str>) {
+        let link_exists = link_target.is_some_and(|target| { (self.link_exists)(target) });
+        let link_color = match link_target {
+            Some(_) if link_exists => Some(LINK_COLOR),
+            Some(_) => Some(BROKEN_LINK_COLOR),
+            None => None,
+        };
+        self.colored_text(text, font_style, link_color, link_target.filter(|_| { link_exists }));
+    }
+
+    fn colored_text(&mut self, text : &str, font_style : FontStyle, link_color : Option<[f32; 4]>, clickable_target : Option<&str>) {
+        let ui = self.ui;
+        match self.line_end {
+            Some(line_end) => ui.same_line(line_end),
+            None => {
+                let [_, y] = ui.cursor_pos();
+                ui.set_cursor_pos([self.line_start, y]);
+            }
+        }
+        let font = ui.push_font(*self.fonts.get(&font_style));
+        let color = link_color.map(|color| { ui.push_style_color(StyleColor::Text, color) });
+        let start = ui.cursor_screen_pos();
+        let (clicked, hovered) = wrapped_text(ui, &ImString::new(text), self.line_start);
+        if self.in_secret {
+            secret_background(ui, start, self.line_start);
+        }
+        if let Some(color) = color {
+            color.pop(ui);
+        }
         font.pop(ui);
-        let mut new_active_styles = Vec::new();
-        for active_style in active_styles {
-            if active_style.end == idx {
-                active_font_style = active_font_style - active_style.style;
-            } else {
-                new_active_styles.push(active_style);
-            }
-        }
-        active_styles = new_active_styles;
-        if Some(&Break{ pos : idx + 1 } ) == breaks.peek() {
-            ui.same_line(offset); 
-            let mut new_pos = ui.cursor_pos();
-            new_pos[1] += ui.current_font_size();
-            ui.set_cursor_pos(new_pos);
-            breaks.next();
-            while Some(&Break{ pos : idx + 1 } ) == breaks.peek() {
-                breaks.next();
-                new_pos[1] += ui.current_font_size();
-                ui.set_cursor_pos(new_pos);
-            }
-        } else {
-            ui.same_line(ui.item_rect_max()[0] - ui.window_pos()[0]); 
+        self.line_end = Some(ui.item_rect_max()[0] - ui.window_pos()[0]);
+        if let Some(target) = clickable_target {
+            if hovered {
+                ui.set_mouse_cursor(Some(MouseCursor::Hand));
+            }
+            if clicked {
+                self.clicked_link = Some(target.to_string());
+            }
         }
     }
-    outer_font.pop(ui);
-    ui.new_line();
 }
 
-pub fn wrapped_text(ui : &Ui, text : &ImString, line_start : f32) {
+fn secret_background(ui : &Ui, start : [f32; 2], line_start : f32) {
+    let [window_x, _] = ui.window_pos();
+    let [end_x, end_y] = ui.item_rect_max();
+    let line_height = ui.text_line_height_with_spacing();
+    let draw_list = ui.get_window_draw_list();
+    if end_y - start[1] <= line_height {
+        draw_list.add_rect(start, [end_x, end_y], SECRET_BACKGROUND).filled(true).build();
+    } else {
+        let first_line_end = start[1] + line_height;
+        draw_list.add_rect(start, [window_x + ui.window_size()[0], first_line_end], SECRET_BACKGROUND).filled(true).build();
+        draw_list.add_rect([window_x + line_start, first_line_end], [end_x, end_y], SECRET_BACKGROUND).filled(true).build();
+    }
+}
+
+fn list_item_marker(ui : &Ui, indent : f32, number : Option<u32>) -> f32 {
+    let [_, y] = ui.cursor_pos();
+    ui.set_cursor_pos([indent, y]);
+    match number {
+        Some(number) => ui.text(ImString::new(format!("{}.", number))),
+        None => ui.bullet(),
+    }
+    let marker_end = ui.item_rect_max()[0] - ui.window_pos()[0];
+    (indent + LIST_MARKER_WIDTH).max(marker_end + LIST_MARKER_SPACING)
+}
+
+pub fn wrapped_text(ui : &Ui, text : &ImString, line_start : f32) -> (bool, bool) {
     let [max_x ,_] = ui.window_size();
     let [offset, start_y] = ui.cursor_pos();
     let wrap_pos  = unsafe { 
@@ -127,12 +467,17 @@ pub fn wrapped_text(ui : &Ui, text : &ImString, line_start : f32) {
         ui.set_cursor_pos([line_start, start_y + ui.text_line_height_with_spacing()]);
         ui.text_wrapped(&first_line);
     }
+    let mut clicked = ui.is_item_clicked(MouseButton::Left);
+    let mut hovered = ui.is_item_hovered();
     ui.set_cursor_pos([line_start, start_y + ui.text_line_height_with_spacing()]);
     let mut rest = &text.to_str()[wrap_pos as usize..];
-    while rest.chars().nth(0) == Some(' ') {
+    while rest.starts_with(' ') {
         rest = &rest[1..];
     }
-    if rest != "" {
+    if !rest.is_empty() {
         ui.text_wrapped(&ImString::new(rest));
+        clicked |= ui.is_item_clicked(MouseButton::Left);
+        hovered |= ui.is_item_hovered();
     }
+    (clicked, hovered)
 }
\ No newline at end of file
@@ -15,7 +15,7 @@ pub struct Fonts {
 impl Fonts {
     pub fn new(fonts : HashMap<FontStyle, FontId>) -> Result<Self, ()> {
         for font_style in FontStyle::all() {
-            if fonts.get(&font_style) == None {
+            if !fonts.contains_key(&font_style) {
                 return Err(())
             }
         }
@@ -45,13 +45,17 @@ pub enum FontStyle {
     Bold,
     Italic,
     BoldItalic,
+    Heading(u8),
+    Code,
 }
 
 impl FontStyle {
     pub fn all() -> Vec<FontStyle>
     {
         use FontStyle::*;
-        vec![Normal, Bold, Italic, BoldItalic]
+        let mut all = vec![Normal, Bold, Italic, BoldItalic, Code];
+        all.extend((1..=gm_unleashed_md::MAX_HEADING_LEVEL).map(Heading));
+        all
     }
 }
 
@@ -60,6 +64,8 @@ impl std::ops::Add<gm_unleashed_md::Style> for FontStyle {
     fn add(self, md_style : gm_unleashed_md::Style) -> Self::Output {
         use FontStyle::*;
         match (self, md_style) {
+             (_, gm_unleashed_md::Style::Heading{ level }) => { Heading(level) }
+             (_, gm_unleashed_md::Style::Code) => { Code }
              (Normal, gm_unleashed_md::Style::Italic) => { Italic }
              (Normal, gm_unleashed_md::Style::Bold) => { Bold }
              (Italic, gm_unleashed_md::Style::Bold) => { BoldItalic }
@@ -78,6 +84,8 @@ impl std::ops::Sub<gm_unleashed_md::Style> for FontStyle {
             (BoldItalic, gm_unleashed_md::Style::Italic) => { Bold }
             (Italic, gm_unleashed_md::Style::Italic) => { Normal }
             (Bold, gm_unleashed_md::Style::Bold) => { Normal }
+            (Heading(_), gm_unleashed_md::Style::Heading{ .. }) => { Normal }
+            (Code, gm_unleashed_md::Style::Code) => { Normal }
             (s, _) => { s }
         }
     }
@@ -86,6 +94,7 @@ impl std::ops::Sub<gm_unleashed_md::Style> for FontStyle {
 impl AppRenderer {
     pub fn new() -> Self {
         const FONT_SIZE : f32 = 24.0;
+        const HEADING_SCALES : [f32; gm_unleashed_md::MAX_HEADING_LEVEL as usize] = [2.0, 1.6, 1.4, 1.2, 1.1, 1.0];
         let event_loop = EventLoop::new();
         let mut imgui = Context::create();
         imgui.set_ini_filename(None);
@@ -102,7 +111,7 @@ impl AppRenderer {
         ).expect("Error in setting up the display");
         let mut renderer = Renderer::init(&mut imgui, &display).expect("Error in setting up the renderer");
         let mut platform = WinitPlatform::init(&mut imgui);
-        platform.attach_window(imgui.io_mut(), &display.gl_window().window(), HiDpiMode::Rounded);
+        platform.attach_window(imgui.io_mut(), display.gl_window().window(), HiDpiMode::Rounded);
 
         let mut fonts = HashMap::new();
         imgui.fonts().add_font( &[
@@ -157,6 +166,30 @@ impl AppRenderer {
                 }),
             },
         ]));       
+        fonts.insert(FontStyle::Code, imgui.fonts().add_font(&[
+            FontSource::TtfData {
+                data: include_bytes!("../assets/dejavu_sans_mono.ttf"),
+                size_pixels: FONT_SIZE,
+                config: Some(FontConfig {
+                    rasterizer_multiply: 1.0,
+                    glyph_ranges: FontGlyphRanges::default(),
+                    ..FontConfig::default()
+                }),
+            },
+        ]));
+        for (idx, scale) in HEADING_SCALES.iter().enumerate() {
+            fonts.insert(FontStyle::Heading(idx as u8 + 1), imgui.fonts().add_font(&[
+                FontSource::TtfData {
+                    data: include_bytes!("../assets/arial_bold.ttf"),
+                    size_pixels: FONT_SIZE * scale,
+                    config: Some(FontConfig {
+                        rasterizer_multiply: 1.0,
+                        glyph_ranges: FontGlyphRanges::default(),
+                        ..FontConfig::default()
+                    }),
+                },
+            ]));
+        }
         
         renderer.reload_font_texture(&mut imgui).unwrap();
     
@@ -184,7 +217,7 @@ impl AppRenderer {
         event_loop.run(move |event, _, control_flow| {
             match event {
                 Event::MainEventsCleared => {
-                    platform.prepare_frame(gui.io_mut(), &display.gl_window().window()).expect("Could not prepare frame.");
+                    platform.prepare_frame(gui.io_mut(), display.gl_window().window()).expect("Could not prepare frame.");
                     display.gl_window().window().request_redraw();
                 }
                 Event::RedrawRequested(_) => {